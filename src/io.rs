@@ -1,12 +1,33 @@
-use crate::Record;
-use anyhow::Context;
-use std::io::BufRead;
+use crate::{
+    Direction, EvalRecord, EvalState, ParamValue, Record, Span, StudyId, StudyRecord, TrialId,
+};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+#[cfg(feature = "hpobench")]
+pub mod hpobench;
+pub mod optuna;
+
+#[cfg(feature = "tdigest")]
+mod digest;
+
+#[cfg(feature = "tdigest")]
+pub use digest::ValueDigest;
 
 pub fn read_records<T: BufRead>(reader: T) -> Records<T> {
     Records {
         line_reader: reader,
         line_no: 0,
         line: String::new(),
+        skip: 0,
+        take: None,
+        scalar_values: false,
     }
 }
 
@@ -15,30 +36,1612 @@ pub struct Records<T> {
     line_reader: T,
     line_no: usize,
     line: String,
+    skip: usize,
+    take: Option<usize>,
+    scalar_values: bool,
 }
 
 impl<T: BufRead> Records<T> {
-    fn read_record(&mut self) -> anyhow::Result<Option<Record>> {
-        self.line.clear();
-        self.line_no += 1;
-        let size = self
-            .line_reader
-            .read_line(&mut self.line)
-            .with_context(|| format!("line={}", self.line_no))?;
-        if size == 0 {
-            Ok(None)
-        } else {
-            let record = serde_json::from_str(&self.line)
-                .with_context(|| format!("line={}", self.line_no))?;
-            Ok(Some(record))
+    /// Skips the first `n` records without parsing them.
+    ///
+    /// Unlike `Iterator::skip`, malformed records are skipped too. Blank lines aren't counted.
+    pub fn skip_lines(mut self, n: usize) -> Self {
+        self.skip += n;
+        self
+    }
+
+    /// Stops after yielding `n` records (excluding the skipped ones). Blank lines aren't counted.
+    pub fn take_lines(mut self, n: usize) -> Self {
+        self.take = Some(self.take.map_or(n, |m| m.min(n)));
+        self
+    }
+
+    /// If enabled, a scalar `value` field of eval records (see [`RecordWriter::scalar_values`])
+    /// is read as a one-element `values` array.
+    pub fn scalar_values(mut self, enabled: bool) -> Self {
+        self.scalar_values = enabled;
+        self
+    }
+
+    fn read_record(&mut self) -> Result<Option<Record>, Error> {
+        if self.take == Some(0) {
+            return Ok(None);
+        }
+        loop {
+            self.line.clear();
+            self.line_no += 1;
+            let size = self
+                .line_reader
+                .read_line(&mut self.line)
+                .map_err(|source| Error::Read {
+                    line: self.line_no,
+                    source,
+                })?;
+            if size == 0 {
+                return Ok(None);
+            }
+            if self.line.trim().is_empty() {
+                continue;
+            }
+            if self.skip > 0 {
+                self.skip -= 1;
+                continue;
+            }
+            if let Some(n) = &mut self.take {
+                *n -= 1;
+            }
+
+            let record = self.parse_line().map_err(|source| {
+                // Only the last line of a stream can lack a newline,
+                // but it may be a complete (yet invalid) record as well.
+                if !self.line.ends_with('\n') && source.is_eof() {
+                    Error::TruncatedTail {
+                        line: self.line_no,
+                        source,
+                    }
+                } else {
+                    Error::Parse {
+                        line: self.line_no,
+                        source,
+                    }
+                }
+            })?;
+            return Ok(Some(record));
+        }
+    }
+}
+
+impl<T> Records<T> {
+    fn parse_line(&self) -> serde_json::Result<Record> {
+        if !self.scalar_values {
+            return serde_json::from_str(&self.line);
         }
+        let mut json: serde_json::Value = serde_json::from_str(&self.line)?;
+        if let Some(eval) = json.get_mut("eval").and_then(|e| e.as_object_mut()) {
+            if !eval.contains_key("values") {
+                if let Some(value) = eval.remove("value") {
+                    eval.insert("values".to_owned(), serde_json::Value::Array(vec![value]));
+                }
+            }
+        }
+        serde_json::from_value(json)
     }
 }
 
 impl<T: BufRead> Iterator for Records<T> {
-    type Item = anyhow::Result<Record>;
+    type Item = Result<Record, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.read_record().transpose()
     }
 }
+
+/// Reads all records from `reader`, detecting whether it's JSON Lines or a JSON array of records.
+///
+/// Compressed (gzip or zstd) inputs are detected but not supported.
+pub fn read_any<R: Read + Seek>(mut reader: R) -> Result<Vec<Record>, Error> {
+    let start = reader.stream_position().map_err(Error::Io)?;
+    let mut prefix = Vec::new();
+    (&mut reader)
+        .take(1024)
+        .read_to_end(&mut prefix)
+        .map_err(Error::Io)?;
+    reader.seek(SeekFrom::Start(start)).map_err(Error::Io)?;
+
+    if prefix.starts_with(&[0x1f, 0x8b]) {
+        return Err(Error::UnsupportedFormat("gzip"));
+    }
+    if prefix.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        return Err(Error::UnsupportedFormat("zstd"));
+    }
+    if prefix.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[') {
+        serde_json::from_reader(BufReader::new(reader)).map_err(|source| Error::Parse {
+            line: source.line(),
+            source,
+        })
+    } else {
+        read_records(BufReader::new(reader)).collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct RecordWriter<W> {
+    writer: W,
+    compact_integers: bool,
+    scalar_values: bool,
+    single_value_studies: BTreeSet<StudyId>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            compact_integers: false,
+            scalar_values: false,
+            single_value_studies: BTreeSet::new(),
+        }
+    }
+
+    /// If enabled, whole-number params are written without a fractional part (e.g., `3` instead of `3.0`).
+    pub fn compact_integers(mut self, enabled: bool) -> Self {
+        self.compact_integers = enabled;
+        self
+    }
+
+    /// If enabled, evals of studies (written before) with exactly one value have a scalar `value` field
+    /// instead of a one-element `values` array. Use [`Records::scalar_values`] to read them back.
+    pub fn scalar_values(mut self, enabled: bool) -> Self {
+        self.scalar_values = enabled;
+        self
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        self.write_json(record).map_err(|source| {
+            let (study, trial) = match record {
+                Record::Study(study) => (study.id.clone(), None),
+                Record::Eval(eval) => (eval.study.clone(), Some(eval.trial)),
+            };
+            Error::Write {
+                study,
+                trial,
+                source,
+            }
+        })
+    }
+
+    fn write_json(&mut self, record: &Record) -> std::io::Result<()> {
+        let scalar_value = match record {
+            Record::Study(study) => {
+                if study.values.len() == 1 {
+                    self.single_value_studies.insert(study.id.clone());
+                } else {
+                    self.single_value_studies.remove(&study.id);
+                }
+                false
+            }
+            Record::Eval(eval) => {
+                self.scalar_values
+                    && eval.values.len() == 1
+                    && self.single_value_studies.contains(&eval.study)
+            }
+        };
+
+        match record {
+            Record::Eval(eval) if self.compact_integers || scalar_value => {
+                let params = eval
+                    .params
+                    .iter()
+                    .map(|&v| {
+                        if self.compact_integers && v.fract() == 0.0 && v.abs() < i64::MAX as f64 {
+                            (v as i64).into()
+                        } else {
+                            v.into()
+                        }
+                    })
+                    .collect();
+                let mut values = eval
+                    .values
+                    .iter()
+                    .map(|v| Some(*v).filter(|v| v.is_finite()));
+                let (values, value) = if scalar_value {
+                    (None, values.next_back())
+                } else {
+                    (Some(values.collect()), None)
+                };
+                let line = EvalLine::Eval {
+                    study: &eval.study,
+                    trial: eval.trial,
+                    state: eval.state,
+                    spans: &eval.spans,
+                    params,
+                    values,
+                    value,
+                };
+                serde_json::to_writer(&mut self.writer, &line)?;
+            }
+            _ => serde_json::to_writer(&mut self.writer, record)?,
+        }
+        writeln!(self.writer)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// `Record::Eval` with params and values rewritten by the modes of `RecordWriter`.
+// The fields are declared in the same order as `EvalRecord` so that the layout of lines doesn't change.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum EvalLine<'a> {
+    Eval {
+        study: &'a StudyId,
+        trial: TrialId,
+        state: EvalState,
+        spans: &'a [Span],
+        params: Vec<serde_json::Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        values: Option<Vec<Option<f64>>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        value: Option<Option<f64>>,
+    },
+}
+
+/// Writes `records` as JSON Lines in a canonical order so that the same content always yields the same bytes.
+///
+/// Study records come first (ordered by id), followed by eval records ordered by study, trial, state and time.
+pub fn write_canonical<W: Write>(mut writer: W, records: &[Record]) -> std::io::Result<()> {
+    let mut lines = records
+        .iter()
+        .map(|r| Ok((r, serde_json::to_string(r)?)))
+        .collect::<serde_json::Result<Vec<_>>>()?;
+    lines.sort_by(|(a, a_json), (b, b_json)| {
+        let order = match (a, b) {
+            (Record::Study(a), Record::Study(b)) => a.id.cmp(&b.id),
+            (Record::Study(_), Record::Eval(_)) => Ordering::Less,
+            (Record::Eval(_), Record::Study(_)) => Ordering::Greater,
+            (Record::Eval(a), Record::Eval(b)) => a
+                .study
+                .cmp(&b.study)
+                .then(a.trial.cmp(&b.trial))
+                .then(a.state.cmp(&b.state))
+                .then_with(|| {
+                    let t = |e: &EvalRecord| e.latest_span_end().unwrap_or(f64::NEG_INFINITY);
+                    t(a).total_cmp(&t(b))
+                }),
+        };
+        order.then_with(|| a_json.cmp(b_json))
+    });
+    for (_, line) in lines {
+        writeln!(writer, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Read {
+        line: usize,
+        source: std::io::Error,
+    },
+    Parse {
+        line: usize,
+        source: serde_json::Error,
+    },
+    /// The last line is an incomplete record, e.g., because the writer was interrupted.
+    TruncatedTail {
+        line: usize,
+        source: serde_json::Error,
+    },
+    UnsupportedFormat(&'static str),
+    Write {
+        study: StudyId,
+        trial: Option<TrialId>,
+        source: std::io::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Read { line, source } => write!(f, "failed to read line {}: {}", line, source),
+            Self::Parse { line, source } => {
+                write!(f, "failed to parse line {}: {}", line, source)
+            }
+            Self::TruncatedTail { line, source } => {
+                write!(f, "truncated record at line {}: {}", line, source)
+            }
+            Self::UnsupportedFormat(format) => write!(f, "unsupported format: {}", format),
+            Self::Write {
+                study,
+                trial: Some(trial),
+                source,
+            } => write!(
+                f,
+                "failed to write eval record (study={:?}, trial={}): {}",
+                study, trial, source
+            ),
+            Self::Write {
+                study,
+                trial: None,
+                source,
+            } => write!(
+                f,
+                "failed to write study record (study={:?}): {}",
+                study, source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Read { source, .. } => Some(source),
+            Self::Parse { source, .. } => Some(source),
+            Self::TruncatedTail { source, .. } => Some(source),
+            Self::UnsupportedFormat(_) => None,
+            Self::Write { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Merges record streams, each of which is ordered by time, into a single time-ordered stream.
+///
+/// The time of an eval record is its latest span end.
+/// Study records and evals without finite spans are emitted as soon as they reach the head of their stream.
+/// Errors are passed through immediately.
+pub fn merge_sorted<I>(readers: Vec<I>) -> impl Iterator<Item = Result<Record, Error>>
+where
+    I: Iterator<Item = Result<Record, Error>>,
+{
+    let mut readers = readers
+        .into_iter()
+        .map(|r| r.peekable())
+        .collect::<Vec<_>>();
+    std::iter::from_fn(move || {
+        let mut next: Option<(usize, f64)> = None;
+        for (i, reader) in readers.iter_mut().enumerate() {
+            let time = match reader.peek() {
+                None => continue,
+                Some(Err(_)) => f64::NEG_INFINITY,
+                Some(Ok(Record::Study(_))) => f64::NEG_INFINITY,
+                Some(Ok(Record::Eval(eval))) => eval.latest_span_end().unwrap_or(f64::NEG_INFINITY),
+            };
+            if next.is_none_or(|(_, t)| time < t) {
+                next = Some((i, time));
+            }
+        }
+        let (i, _) = next?;
+        readers[i].next()
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlatEval {
+    pub study: StudyId,
+    pub trial: TrialId,
+    pub state: EvalState,
+    pub params: Vec<(String, ParamValue)>,
+    pub values: Vec<(String, f64)>,
+
+    /// Serialized as seconds.
+    #[serde(serialize_with = "serialize_secs")]
+    pub wall_clock: Option<Duration>,
+}
+
+fn serialize_secs<S>(d: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    d.map(|d| d.as_secs_f64()).serialize(serializer)
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Columns {
+    pub trial: Vec<TrialId>,
+    pub state: Vec<EvalState>,
+    pub params: BTreeMap<String, Vec<f64>>,
+    pub values: BTreeMap<String, Vec<f64>>,
+}
+
+pub fn interim_monotonic(
+    evals: &[EvalRecord],
+    study: &StudyId,
+    trial: TrialId,
+    value_index: usize,
+    direction: Direction,
+) -> bool {
+    let mut prev: Option<f64> = None;
+    for v in evals
+        .iter()
+        .filter(|e| e.study == *study && e.trial == trial)
+        .filter(|e| e.state.is_interm() || e.state.is_complete())
+        .filter_map(|e| e.values.get(value_index).copied())
+        .filter(|v| !v.is_nan())
+    {
+        if let Some(p) = prev {
+            if direction.better(p, v) != v {
+                return false;
+            }
+        }
+        prev = Some(v);
+    }
+    true
+}
+
+/// Counts the lines of a JSON Lines file without parsing them, ignoring trailing blank lines.
+///
+/// Blank lines between records are counted, so the result is the line number of the last record.
+pub fn count_lines<P: AsRef<Path>>(path: P) -> std::io::Result<usize> {
+    let (lines, _) = scan_lines(path)?;
+    Ok(lines)
+}
+
+/// Counts the non-blank lines of a JSON Lines file without parsing them.
+///
+/// This is the number of records [`read_records`] yields if every line is valid.
+pub fn estimate_records<P: AsRef<Path>>(path: P) -> std::io::Result<usize> {
+    let (_, records) = scan_lines(path)?;
+    Ok(records)
+}
+
+// Returns the number of the last non-blank line and the number of non-blank lines.
+fn scan_lines<P: AsRef<Path>>(path: P) -> std::io::Result<(usize, usize)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut lines = 0;
+    let mut last_record_line = 0;
+    let mut records = 0;
+    let mut in_line = false;
+    let mut blank = true;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        for &b in buf {
+            if b == b'\n' {
+                lines += 1;
+                if !blank {
+                    last_record_line = lines;
+                    records += 1;
+                }
+                in_line = false;
+                blank = true;
+            } else {
+                in_line = true;
+                blank = blank && b.is_ascii_whitespace();
+            }
+        }
+        let n = buf.len();
+        reader.consume(n);
+    }
+    if in_line && !blank {
+        last_record_line = lines + 1;
+        records += 1;
+    }
+    Ok((last_record_line, records))
+}
+
+/// Groups trials by their multi-fidelity rung.
+///
+/// The rung of a trial is the number of its interim records,
+/// and each group holds the latest record of every trial in that rung.
+///
+/// Note that this doesn't agree with [`StudyRecord::rung_of`],
+/// which reads the param named by the [`crate::RUNG_PARAM_ATTR`] attribute instead.
+pub fn group_by_rung(evals: &[EvalRecord]) -> BTreeMap<usize, Vec<&EvalRecord>> {
+    let mut trials: BTreeMap<(&StudyId, TrialId), (usize, &EvalRecord)> = BTreeMap::new();
+    for eval in evals {
+        let entry = trials.entry((&eval.study, eval.trial)).or_insert((0, eval));
+        if eval.state.is_interm() {
+            entry.0 += 1;
+        }
+        entry.1 = eval;
+    }
+
+    let mut groups: BTreeMap<usize, Vec<&EvalRecord>> = BTreeMap::new();
+    for (rung, eval) in trials.into_values() {
+        groups.entry(rung).or_default().push(eval);
+    }
+    groups
+}
+
+/// Returns the trial after which the running best never improves by more than `tol` (relative).
+///
+/// Improvements are measured against the best value at the returned trial (the anchor) rather than
+/// the previous best, so that a series of small improvements still counts once it adds up to more than `tol`.
+pub fn convergence_trial(
+    evals: &[EvalRecord],
+    value_index: usize,
+    direction: Direction,
+    tol: f64,
+) -> Option<TrialId> {
+    let mut anchor: Option<(TrialId, f64)> = None;
+    for (eval, v) in complete_values(evals, value_index) {
+        let moves =
+            anchor.is_none_or(|(_, a)| improves(direction, v, a) && (v - a).abs() > tol * a.abs());
+        if moves {
+            anchor = Some((eval.trial, v));
+        }
+    }
+    anchor.map(|(trial, _)| trial)
+}
+
+/// Returns the trapezoidal area under the running-best curve of the first `budget` complete trials,
+/// normalized by the budget so that a constant curve yields its value.
+///
+/// If fewer than `budget` trials completed, the last running best is extended to the end of the budget.
+/// Lower is better for `Direction::Minimize` and higher is better for `Direction::Maximize`.
+/// Returns NaN if `budget` is zero or no trial completed.
+pub fn auc_convergence(
+    evals: &[EvalRecord],
+    value_index: usize,
+    direction: Direction,
+    budget: usize,
+) -> f64 {
+    let mut curve = complete_values(evals, value_index)
+        .map(|(_, v)| v)
+        .scan(None, |best: &mut Option<f64>, v| {
+            let b = best.map_or(v, |b| direction.better(b, v));
+            *best = Some(b);
+            Some(b)
+        })
+        .take(budget)
+        .collect::<Vec<_>>();
+    let Some(&last) = curve.last() else {
+        return f64::NAN;
+    };
+    curve.resize(budget, last);
+    if budget == 1 {
+        return last;
+    }
+
+    let area = curve.windows(2).map(|w| (w[0] + w[1]) / 2.0).sum::<f64>();
+    area / (budget - 1) as f64
+}
+
+/// Appends the value of the last `ValueDef` of `study`, computed by `f` from the other values, to each eval.
+///
+/// Evals that already have all of the values (or more) are left as is,
+/// and missing values of the others are filled with NaN before `f` is applied.
+pub fn apply_derived<F>(study: &StudyRecord, evals: &mut [EvalRecord], f: F)
+where
+    F: Fn(&[f64]) -> f64,
+{
+    let Some(arity) = study.values.len().checked_sub(1) else {
+        return;
+    };
+    for eval in evals
+        .iter_mut()
+        .filter(|e| e.study == study.id && e.values.len() <= arity)
+    {
+        eval.values.resize(arity, f64::NAN);
+        let v = f(&eval.values);
+        eval.values.push(v);
+    }
+}
+
+/// Clamps the finite values of the column to its `lower_pct`-th and `upper_pct`-th percentiles.
+///
+/// Percentiles are given in `[0, 100]` and linearly interpolated between the sorted values.
+pub fn winsorize(evals: &mut [EvalRecord], value_index: usize, lower_pct: f64, upper_pct: f64) {
+    let mut sorted = evals
+        .iter()
+        .filter_map(|e| e.values.get(value_index).copied())
+        .filter(|v| v.is_finite())
+        .collect::<Vec<_>>();
+    if sorted.is_empty() {
+        return;
+    }
+    sorted.sort_by(f64::total_cmp);
+
+    let percentile = |pct: f64| {
+        let rank = (pct.clamp(0.0, 100.0) / 100.0) * (sorted.len() - 1) as f64;
+        let (i, frac) = (rank.floor() as usize, rank.fract());
+        match sorted.get(i + 1) {
+            Some(next) => sorted[i] + (next - sorted[i]) * frac,
+            None => sorted[i],
+        }
+    };
+    let lower = percentile(lower_pct);
+    let upper = percentile(upper_pct);
+    for v in evals
+        .iter_mut()
+        .filter_map(|e| e.values.get_mut(value_index))
+        .filter(|v| v.is_finite())
+    {
+        *v = v.clamp(lower, upper.max(lower));
+    }
+}
+
+/// Yields each eval of `study` in order, paired with the incumbent (the best complete eval so far).
+pub fn replay<'a>(
+    study: &'a StudyRecord,
+    evals: &'a [EvalRecord],
+    value_index: usize,
+    direction: Direction,
+) -> impl Iterator<Item = (&'a EvalRecord, Option<&'a EvalRecord>)> + 'a {
+    let mut incumbent: Option<(&EvalRecord, f64)> = None;
+    evals
+        .iter()
+        .filter(move |e| e.study == study.id)
+        .map(move |eval| {
+            if eval.state.is_complete() {
+                if let Some(&v) = eval.values.get(value_index) {
+                    if !v.is_nan() && incumbent.is_none_or(|(_, best)| improves(direction, v, best))
+                    {
+                        incumbent = Some((eval, v));
+                    }
+                }
+            }
+            (eval, incumbent.map(|(e, _)| e))
+        })
+}
+
+/// Predicts the asymptote of a learning curve given as `(step, value)` pairs.
+///
+/// The curve is fitted to `a - b * exp(-c * t)` by least squares:
+/// for each candidate rate `c` (searched over a log-spaced grid and then refined by golden-section search),
+/// `a` and `b` have a closed-form solution, and the `c` with the smallest squared error is chosen.
+/// The predicted final value is `a`.
+///
+/// Returns `None` if there are fewer than three finite points, if the best rate lies on the boundary of the search
+/// range (i.e., the curve doesn't look saturating), or if the fitted curve doesn't improve in `direction`.
+pub fn extrapolate_final(curve: &[(f64, f64)], direction: Direction) -> Option<f64> {
+    let points = curve
+        .iter()
+        .copied()
+        .filter(|(t, y)| t.is_finite() && y.is_finite())
+        .collect::<Vec<_>>();
+    if points.len() < 3 {
+        return None;
+    }
+    let t0 = points.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+    let t1 = points.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+    let span = t1 - t0;
+    if span <= 0.0 {
+        return None;
+    }
+
+    // Returns `(a, b, squared error)` for the rate `c = exp(log_c) / span`.
+    let fit = |log_c: f64| {
+        let c = log_c.exp() / span;
+        let xs = points.iter().map(|(t, _)| (-c * (t - t0)).exp());
+        let n = points.len() as f64;
+        let mx = xs.clone().sum::<f64>() / n;
+        let my = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let (mut sxy, mut sxx) = (0.0, 0.0);
+        for (x, (_, y)) in xs.clone().zip(&points) {
+            sxy += (x - mx) * (y - my);
+            sxx += (x - mx) * (x - mx);
+        }
+        if sxx <= 0.0 {
+            return None;
+        }
+        let k = sxy / sxx;
+        let a = my - k * mx;
+        let sse = xs
+            .zip(&points)
+            .map(|(x, (_, y))| (a + k * x - y).powi(2))
+            .sum::<f64>();
+        Some((a, -k, sse))
+    };
+
+    const GRID: usize = 61;
+    let (lo, hi) = ((1e-3f64).ln(), (1e3f64).ln());
+    let log_cs = (0..GRID)
+        .map(|i| lo + (hi - lo) * i as f64 / (GRID - 1) as f64)
+        .collect::<Vec<_>>();
+    let (best, _) = log_cs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &log_c)| Some((i, fit(log_c)?.2)))
+        .min_by(|x, y| x.1.total_cmp(&y.1))?;
+    if best == 0 || best == GRID - 1 {
+        return None;
+    }
+
+    let sse = |log_c: f64| fit(log_c).map_or(f64::INFINITY, |f| f.2);
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut l, mut r) = (log_cs[best - 1], log_cs[best + 1]);
+    for _ in 0..100 {
+        let m1 = r - ratio * (r - l);
+        let m2 = l + ratio * (r - l);
+        if sse(m1) < sse(m2) {
+            r = m2;
+        } else {
+            l = m1;
+        }
+    }
+    let (a, b, _) = fit((l + r) / 2.0)?;
+    let improving = if direction.is_maximize() {
+        b > 0.0
+    } else {
+        b < 0.0
+    };
+    if a.is_finite() && improving {
+        Some(a)
+    } else {
+        None
+    }
+}
+
+/// Returns, for each complete trial, how many of the last `window` complete trials (including itself) improved the best value.
+///
+/// The first complete trial counts as an improvement.
+pub fn improvements_in_window(
+    evals: &[EvalRecord],
+    value_index: usize,
+    direction: Direction,
+    window: usize,
+) -> Vec<usize> {
+    let improved = improvements(evals, value_index, direction).collect::<Vec<_>>();
+    (0..improved.len())
+        .map(|i| {
+            let start = (i + 1).saturating_sub(window);
+            improved[start..=i].iter().filter(|&&b| b).count()
+        })
+        .collect()
+}
+
+/// Returns the fraction of complete trials that improved the best value (the first one counts as an improvement).
+///
+/// Returns NaN if no trial completed.
+pub fn hit_rate(evals: &[EvalRecord], value_index: usize, direction: Direction) -> f64 {
+    let (hits, total) = improvements(evals, value_index, direction)
+        .fold((0, 0), |(hits, total), improved| {
+            (hits + usize::from(improved), total + 1)
+        });
+    if total == 0 {
+        f64::NAN
+    } else {
+        hits as f64 / total as f64
+    }
+}
+
+// Yields whether each complete trial improved the best value so far.
+fn improvements(
+    evals: &[EvalRecord],
+    value_index: usize,
+    direction: Direction,
+) -> impl '_ + Iterator<Item = bool> {
+    let mut best: Option<f64> = None;
+    complete_values(evals, value_index).map(move |(_, v)| {
+        let improved = best.is_none_or(|b| improves(direction, v, b));
+        if improved {
+            best = Some(v);
+        }
+        improved
+    })
+}
+
+/// Returns the first trial whose number of params differs from that of the first eval,
+/// along with the expected and actual numbers.
+pub fn detect_arity_drift(evals: &[EvalRecord]) -> Option<(TrialId, usize, usize)> {
+    let expected = evals.first()?.params.len();
+    evals
+        .iter()
+        .find(|e| e.params.len() != expected)
+        .map(|e| (e.trial, expected, e.params.len()))
+}
+
+/// Returns the running total of wall-clock time after each complete trial, in trial order.
+///
+/// Trials without valid spans contribute no time.
+pub fn cumulative_time(evals: &[EvalRecord]) -> Vec<(TrialId, Duration)> {
+    let mut completes = evals
+        .iter()
+        .filter(|e| e.state.is_complete())
+        .collect::<Vec<_>>();
+    completes.sort_by_key(|e| e.trial);
+    completes
+        .into_iter()
+        .scan(Duration::ZERO, |total, e| {
+            *total += e.wall_clock().unwrap_or_default();
+            Some((e.trial, *total))
+        })
+        .collect()
+}
+
+/// Returns the infeasible evals that nonetheless report finite values, which usually indicates a producer bug.
+///
+/// Since no schema is consulted, evals reporting only constraint values (see `ValueDef::constraint`) are flagged too.
+pub fn lint_infeasible(evals: &[EvalRecord]) -> Vec<(StudyId, TrialId)> {
+    evals
+        .iter()
+        .filter(|e| e.state.is_infeasible() && e.values.iter().any(|v| v.is_finite()))
+        .map(|e| (e.study.clone(), e.trial))
+        .collect()
+}
+
+/// Counts complete trials per fixed-width time bucket of their completion time (i.e., latest span end).
+///
+/// Buckets are aligned to multiples of `bucket_seconds` and returned as `(bucket start, count)`
+/// pairs in time order. Empty buckets are omitted.
+pub fn throughput(evals: &[EvalRecord], bucket_seconds: f64) -> Vec<(f64, usize)> {
+    if !bucket_seconds.is_finite() || bucket_seconds <= 0.0 {
+        return Vec::new();
+    }
+    let mut counts: BTreeMap<i64, usize> = BTreeMap::new();
+    for t in evals
+        .iter()
+        .filter(|e| e.state.is_complete())
+        .filter_map(|e| e.latest_span_end())
+    {
+        *counts
+            .entry((t / bucket_seconds).floor() as i64)
+            .or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .map(|(b, n)| (b as f64 * bucket_seconds, n))
+        .collect()
+}
+
+/// Returns the best complete eval among those finished within `time_budget` seconds of cumulative wall-clock time.
+///
+/// The cumulative time is computed in the same way as [`cumulative_time`].
+pub fn best_within_time(
+    evals: &[EvalRecord],
+    value_index: usize,
+    direction: Direction,
+    time_budget: f64,
+) -> Option<&EvalRecord> {
+    let mut completes = evals
+        .iter()
+        .filter(|e| e.state.is_complete())
+        .collect::<Vec<_>>();
+    completes.sort_by_key(|e| e.trial);
+
+    let mut total = 0.0;
+    let mut best: Option<(&EvalRecord, f64)> = None;
+    for eval in completes {
+        total += eval.wall_clock().unwrap_or_default().as_secs_f64();
+        if total > time_budget {
+            break;
+        }
+        let Some(&v) = eval.values.get(value_index) else {
+            continue;
+        };
+        if !v.is_nan() && best.is_none_or(|(_, b)| improves(direction, v, b)) {
+            best = Some((eval, v));
+        }
+    }
+    best.map(|(e, _)| e)
+}
+
+/// Returns the params of each record of the trial, in order.
+pub fn param_trajectory(evals: &[EvalRecord], study: &StudyId, trial: TrialId) -> Vec<Vec<f64>> {
+    evals
+        .iter()
+        .filter(|e| e.study == *study && e.trial == trial)
+        .map(|e| e.params.clone())
+        .collect()
+}
+
+/// Groups studies by their `group` label. Studies without a label are omitted.
+pub fn group_studies_by_group(studies: &[StudyRecord]) -> BTreeMap<String, Vec<&StudyRecord>> {
+    let mut groups: BTreeMap<String, Vec<&StudyRecord>> = BTreeMap::new();
+    for study in studies {
+        if let Some(group) = &study.group {
+            groups.entry(group.clone()).or_default().push(study);
+        }
+    }
+    groups
+}
+
+fn complete_values(
+    evals: &[EvalRecord],
+    value_index: usize,
+) -> impl '_ + Iterator<Item = (&EvalRecord, f64)> {
+    evals
+        .iter()
+        .filter(|e| e.state.is_complete())
+        .filter_map(move |e| Some((e, *e.values.get(value_index)?)))
+        .filter(|(_, v)| !v.is_nan())
+}
+
+fn improves(direction: Direction, v: f64, best: f64) -> bool {
+    if direction.is_minimize() {
+        v < best
+    } else {
+        v > best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{eval, study};
+
+    fn temp_file(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("hporecord-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn count_lines_ignores_trailing_blank_lines() {
+        for (name, content, lines, records) in [
+            ("trailing-blank", "a\nb\n\n", 2, 2),
+            ("inner-blank", "a\n\nb\n \n\n", 3, 2),
+            ("no-newline", "a\nb", 2, 2),
+            ("empty", "", 0, 0),
+        ] {
+            let path = temp_file(name, content);
+            assert_eq!(count_lines(&path).unwrap(), lines, "{:?}", content);
+            assert_eq!(estimate_records(&path).unwrap(), records, "{:?}", content);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn interim_monotonic_detects_regressions() {
+        let study = "s".to_owned();
+        let evals = vec![
+            eval(0, EvalState::Interim, vec![], vec![0.9]),
+            eval(1, EvalState::Interim, vec![], vec![0.1]),
+            eval(0, EvalState::Interim, vec![], vec![0.5]),
+            eval(0, EvalState::Interim, vec![], vec![f64::NAN]),
+            eval(0, EvalState::Interim, vec![], vec![0.5]),
+            eval(0, EvalState::Complete, vec![], vec![0.3]),
+            eval(1, EvalState::Interim, vec![], vec![0.4]),
+            eval(1, EvalState::Complete, vec![], vec![0.2]),
+        ];
+        assert!(interim_monotonic(&evals, &study, 0, 0, Direction::Minimize));
+        assert!(!interim_monotonic(
+            &evals,
+            &study,
+            0,
+            0,
+            Direction::Maximize
+        ));
+        assert!(!interim_monotonic(
+            &evals,
+            &study,
+            1,
+            0,
+            Direction::Minimize
+        ));
+        assert!(interim_monotonic(&evals, &study, 2, 0, Direction::Minimize));
+    }
+
+    #[test]
+    fn group_by_rung_counts_interim_records() {
+        let evals = vec![
+            eval(0, EvalState::Interim, vec![], vec![0.9]),
+            eval(1, EvalState::Interim, vec![], vec![0.8]),
+            eval(0, EvalState::Interim, vec![], vec![0.7]),
+            eval(2, EvalState::Complete, vec![], vec![0.6]),
+            eval(1, EvalState::Complete, vec![], vec![0.5]),
+            eval(0, EvalState::Complete, vec![], vec![0.4]),
+        ];
+        let groups = group_by_rung(&evals)
+            .into_iter()
+            .map(|(rung, es)| (rung, es.iter().map(|e| e.trial).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(groups, vec![(0, vec![2]), (1, vec![1]), (2, vec![0])]);
+        assert!(group_by_rung(&evals)[&2][0].state.is_complete());
+    }
+
+    #[test]
+    fn merge_sorted_interleaves_streams_by_time() {
+        let timed = |trial, end| {
+            let mut e = eval(trial, EvalState::Complete, vec![], vec![]);
+            e.spans = vec![crate::Span::new(0.0, end)];
+            Ok(Record::Eval(e))
+        };
+        let a = vec![timed(0, 1.0), timed(2, 3.0), timed(4, 5.0)];
+        let b = vec![timed(1, 2.0), timed(3, 4.0)];
+
+        let trials = merge_sorted(vec![a.into_iter(), b.into_iter()])
+            .map(|r| match r.unwrap() {
+                Record::Eval(e) => e.trial,
+                Record::Study(_) => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(trials, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn convergence_trial_ignores_improvements_below_tol() {
+        let values = [10.0, 8.0, 8.5, 6.0, 5.0, 4.5, 4.2, 3.0, 2.999, 3.5, 2.9985];
+        let evals = values
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| eval(i as TrialId, EvalState::Complete, vec![], vec![v]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            convergence_trial(&evals, 0, Direction::Minimize, 0.01),
+            Some(7)
+        );
+        assert_eq!(
+            convergence_trial(&evals, 0, Direction::Minimize, 0.0),
+            Some(10)
+        );
+        assert_eq!(convergence_trial(&[], 0, Direction::Minimize, 0.01), None);
+    }
+
+    #[test]
+    fn convergence_trial_counts_accumulated_small_improvements() {
+        // The best improves by 0.5% per trial, from 100 down to about 37.
+        let values = (0..200)
+            .map(|i| 100.0 * 0.995f64.powi(i))
+            .collect::<Vec<_>>();
+        let evals = completes(&values);
+        let trial = convergence_trial(&evals, 0, Direction::Minimize, 0.01).unwrap();
+        assert!(trial >= 197, "{}", trial);
+        assert_eq!(
+            convergence_trial(&evals, 0, Direction::Minimize, 0.5),
+            Some(139)
+        );
+    }
+
+    const STUDY_LINE: &str = r#"{"study":{"id":"s","spans":[],"params":[],"values":[]}}"#;
+
+    #[test]
+    fn half_written_last_record_is_a_truncated_tail() {
+        let input = format!(
+            "{}\n{}",
+            STUDY_LINE, r#"{"eval":{"study":"s","trial":0,"sta"#
+        );
+        let mut records = read_records(input.as_bytes());
+        assert!(matches!(records.next(), Some(Ok(Record::Study(_)))));
+        assert!(matches!(
+            records.next(),
+            Some(Err(Error::TruncatedTail { line: 2, .. }))
+        ));
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn invalid_last_record_without_newline_is_a_parse_error() {
+        let eval = r#"{"eval":{"study":"s","trial":0,"state":"BOGUS","spans":[],"params":[],"values":[]}}"#;
+        for input in [
+            format!("{}\n{}", STUDY_LINE, eval),
+            format!("{}\n{}\n", STUDY_LINE, eval),
+            format!("{}\n{}\n", STUDY_LINE, r#"{"eval":{"study":"s""#),
+        ] {
+            let result = read_records(input.as_bytes()).nth(1);
+            assert!(
+                matches!(result, Some(Err(Error::Parse { line: 2, .. }))),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    fn write_lines<W: Write>(mut writer: RecordWriter<W>, records: &[Record]) -> W {
+        for record in records {
+            writer.write_record(record).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn integer_params_round_trip_and_compact_mode_drops_fraction() {
+        for params in ["[3,0.5,null]", "[3.0,0.5,null]"] {
+            let line = format!(
+                r#"{{"eval":{{"study":"s","trial":0,"state":"COMPLETE","spans":[],"params":{},"values":[1.0]}}}}"#,
+                params
+            );
+            let record = read_records(line.as_bytes()).next().unwrap().unwrap();
+            let Record::Eval(eval) = &record else {
+                panic!("{:?}", record);
+            };
+            assert_eq!(eval.params[..2], [3.0, 0.5]);
+            assert!(eval.params[2].is_nan());
+
+            let normal = write_lines(RecordWriter::new(Vec::new()), std::slice::from_ref(&record));
+            let compact = write_lines(
+                RecordWriter::new(Vec::new()).compact_integers(true),
+                &[record],
+            );
+            assert_eq!(
+                String::from_utf8(normal).unwrap(),
+                r#"{"eval":{"study":"s","trial":0,"state":"COMPLETE","spans":[],"params":[3.0,0.5,null],"values":[1.0]}}
+"#
+            );
+            assert_eq!(
+                String::from_utf8(compact).unwrap(),
+                r#"{"eval":{"study":"s","trial":0,"state":"COMPLETE","spans":[],"params":[3,0.5,null],"values":[1.0]}}
+"#
+            );
+        }
+    }
+
+    fn completes(values: &[f64]) -> Vec<EvalRecord> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| eval(i as TrialId, EvalState::Complete, vec![], vec![v]))
+            .collect()
+    }
+
+    #[test]
+    fn auc_convergence_of_a_small_example() {
+        let evals = completes(&[4.0, 5.0, 2.0, 3.0]);
+        // Running best: [4, 4, 2, 2], extended to [4, 4, 2, 2, 2].
+        assert_eq!(
+            auc_convergence(&evals, 0, Direction::Minimize, 5),
+            11.0 / 4.0
+        );
+        assert_eq!(
+            auc_convergence(&evals, 0, Direction::Minimize, 3),
+            7.0 / 2.0
+        );
+        assert_eq!(auc_convergence(&evals, 0, Direction::Minimize, 1), 4.0);
+        // Running best: [4, 5, 5, 5].
+        assert_eq!(
+            auc_convergence(&evals, 0, Direction::Maximize, 4),
+            14.5 / 3.0
+        );
+        assert!(auc_convergence(&evals, 0, Direction::Minimize, 0).is_nan());
+        assert!(auc_convergence(&[], 0, Direction::Minimize, 3).is_nan());
+    }
+
+    #[test]
+    fn apply_derived_leaves_complete_and_longer_evals_alone() {
+        let study = study(
+            Vec::new(),
+            vec![
+                crate::ValueDef::new("a", Direction::Minimize),
+                crate::ValueDef::new("b", Direction::Minimize),
+            ],
+        );
+        let mut evals = vec![
+            eval(0, EvalState::Complete, vec![], vec![1.0]),
+            eval(1, EvalState::Complete, vec![], vec![1.0, 5.0]),
+            eval(2, EvalState::Complete, vec![], vec![1.0, 2.0, 3.0, 4.0]),
+            eval(3, EvalState::Complete, vec![], vec![]),
+        ];
+        evals[0].study = "other".to_owned();
+        apply_derived(&study, &mut evals, |v| v[0] * 10.0);
+
+        assert_eq!(evals[0].values, vec![1.0]);
+        assert_eq!(evals[1].values, vec![1.0, 5.0]);
+        assert_eq!(evals[2].values, vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(evals[3].values.len(), 2);
+        assert!(evals[3].values.iter().all(|v| v.is_nan()));
+    }
+
+    #[test]
+    fn read_any_accepts_json_arrays_and_json_lines() {
+        let eval_line = r#"{"eval":{"study":"s","trial":0,"state":"COMPLETE","spans":[],"params":[],"values":[1.0]}}"#;
+        let jsonl = format!("{}\n\n{}\n", STUDY_LINE, eval_line);
+        let array = format!("  [{},\n{}]", STUDY_LINE, eval_line);
+
+        let from_jsonl = read_any(std::io::Cursor::new(jsonl)).unwrap();
+        let from_array = read_any(std::io::Cursor::new(array)).unwrap();
+        assert_eq!(from_jsonl.len(), 2);
+        assert_eq!(from_jsonl, from_array);
+
+        let gzip = std::io::Cursor::new(vec![0x1f, 0x8b, 0x08, 0x00]);
+        assert!(matches!(
+            read_any(gzip),
+            Err(Error::UnsupportedFormat("gzip"))
+        ));
+    }
+
+    #[test]
+    fn write_canonical_ignores_input_order() {
+        let study = |id: &str| {
+            let mut s = read_records(STUDY_LINE.as_bytes()).next().unwrap().unwrap();
+            if let Record::Study(s) = &mut s {
+                s.id = id.to_owned();
+            }
+            s
+        };
+        let records = vec![
+            Record::Eval(eval(1, EvalState::Complete, vec![0.5], vec![f64::NAN])),
+            study("t"),
+            Record::Eval(eval(0, EvalState::Complete, vec![0.1], vec![1.0])),
+            Record::Eval(eval(0, EvalState::Interim, vec![0.1], vec![2.0])),
+            study("s"),
+        ];
+        let mut reversed = records.clone();
+        reversed.reverse();
+
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        write_canonical(&mut a, &records).unwrap();
+        write_canonical(&mut b, &reversed).unwrap();
+        assert_eq!(a, b);
+
+        let text = String::from_utf8(a).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].contains(r#""id":"s""#));
+        assert!(lines[1].contains(r#""id":"t""#));
+        assert!(lines[2].contains(r#""trial":0,"state":"INTERIM""#));
+        assert!(lines[3].contains(r#""trial":0,"state":"COMPLETE""#));
+        assert!(lines[4].contains(r#""trial":1,"#) && lines[4].contains(r#""values":[null]"#));
+    }
+
+    #[test]
+    fn skip_lines_and_take_lines_count_records() {
+        let eval_line = |trial| {
+            format!(
+                r#"{{"eval":{{"study":"s","trial":{},"state":"COMPLETE","spans":[],"params":[],"values":[1.0]}}}}"#,
+                trial
+            )
+        };
+        let input = format!(
+            "\n{}\n\n{}\n{}\n\n{}\n",
+            STUDY_LINE,
+            eval_line(0),
+            eval_line(1),
+            eval_line(2)
+        );
+        let trials = read_records(input.as_bytes())
+            .skip_lines(1)
+            .take_lines(2)
+            .map(|r| match r.unwrap() {
+                Record::Eval(e) => e.trial,
+                Record::Study(_) => panic!("the study line must be skipped"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(trials, vec![0, 1]);
+
+        // Skipped records aren't parsed, so malformed ones don't cause errors.
+        let input = format!("not json\n{}\n", eval_line(3));
+        let records = read_records(input.as_bytes())
+            .skip_lines(1)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn winsorize_clamps_an_outlier_to_the_95th_percentile() {
+        let mut values = (1..=20).map(f64::from).collect::<Vec<_>>();
+        values.push(1000.0);
+        values.push(f64::NAN);
+        let mut evals = completes(&values);
+
+        winsorize(&mut evals, 0, 5.0, 95.0);
+        // 21 finite values: the 95th percentile is the 20th smallest and the 5th is the 2nd smallest.
+        assert_eq!(evals[20].values[0], 20.0);
+        assert_eq!(evals[0].values[0], 2.0);
+        assert_eq!(evals[10].values[0], 11.0);
+        assert!(evals[21].values[0].is_nan());
+
+        let mut evals = completes(&[0.0, 10.0]);
+        winsorize(&mut evals, 0, 0.0, 25.0);
+        assert_eq!(evals[1].values[0], 2.5);
+    }
+
+    #[test]
+    fn replay_updates_the_incumbent_on_improving_completes() {
+        let study = study(
+            Vec::new(),
+            vec![crate::ValueDef::new("loss", Direction::Minimize)],
+        );
+        let evals = vec![
+            eval(0, EvalState::Interim, vec![], vec![0.1]),
+            eval(0, EvalState::Complete, vec![], vec![0.5]),
+            eval(1, EvalState::Complete, vec![], vec![0.7]),
+            eval(2, EvalState::Failed, vec![], vec![0.0]),
+            eval(3, EvalState::Complete, vec![], vec![f64::NAN]),
+            eval(4, EvalState::Complete, vec![], vec![0.3]),
+            eval(5, EvalState::Complete, vec![], vec![0.3]),
+        ];
+        let steps = replay(&study, &evals, 0, Direction::Minimize)
+            .map(|(e, incumbent)| (e.trial, incumbent.map(|i| i.trial)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            steps,
+            vec![
+                (0, None),
+                (0, Some(0)),
+                (1, Some(0)),
+                (2, Some(0)),
+                (3, Some(0)),
+                (4, Some(4)),
+                (5, Some(4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn extrapolate_final_recovers_the_asymptote() {
+        let curve = (1..=20)
+            .map(|t| {
+                let t = f64::from(t);
+                (t, 0.9 - 0.5 * (-0.3 * t).exp())
+            })
+            .collect::<Vec<_>>();
+        let a = extrapolate_final(&curve, Direction::Maximize).unwrap();
+        assert!((a - 0.9).abs() < 1e-3, "{}", a);
+
+        let curve = (0..15)
+            .map(|t| {
+                let t = f64::from(t) * 10.0;
+                (t, 0.2 + 0.8 * (-0.05 * t).exp())
+            })
+            .collect::<Vec<_>>();
+        let a = extrapolate_final(&curve, Direction::Minimize).unwrap();
+        assert!((a - 0.2).abs() < 1e-3, "{}", a);
+    }
+
+    #[test]
+    fn extrapolate_final_rejects_unfit_curves() {
+        // Fewer than three finite points.
+        assert_eq!(
+            extrapolate_final(&[(0.0, 1.0), (1.0, 0.5)], Direction::Minimize),
+            None
+        );
+        assert_eq!(
+            extrapolate_final(
+                &[(0.0, 1.0), (1.0, 0.5), (2.0, f64::NAN)],
+                Direction::Minimize
+            ),
+            None
+        );
+
+        // A straight line is best fitted by the smallest rate on the grid, i.e., it doesn't saturate.
+        let line = (0..10)
+            .map(|t| (f64::from(t), 1.0 - 0.05 * f64::from(t)))
+            .collect::<Vec<_>>();
+        assert_eq!(extrapolate_final(&line, Direction::Minimize), None);
+
+        // A saturating curve that gets worse in the given direction.
+        let rising = (1..=20)
+            .map(|t| {
+                let t = f64::from(t);
+                (t, 0.9 - 0.5 * (-0.3 * t).exp())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(extrapolate_final(&rising, Direction::Minimize), None);
+    }
+
+    #[test]
+    fn improvements_in_window_counts_recent_improvements() {
+        // Improvements: yes, yes, no, yes, no, yes, no.
+        let evals = completes(&[5.0, 4.0, 6.0, 3.0, 3.0, 2.0, 7.0]);
+        assert_eq!(
+            improvements_in_window(&evals, 0, Direction::Minimize, 3),
+            vec![1, 2, 2, 2, 1, 2, 1]
+        );
+        assert_eq!(
+            improvements_in_window(&evals, 0, Direction::Minimize, 100),
+            vec![1, 2, 2, 3, 3, 4, 4]
+        );
+        assert_eq!(
+            improvements_in_window(&evals, 0, Direction::Minimize, 0),
+            vec![0; 7]
+        );
+    }
+
+    #[test]
+    fn detect_arity_drift_finds_the_first_drifting_trial() {
+        let mut evals = (0..8)
+            .map(|trial| eval(trial, EvalState::Complete, vec![0.1, 0.2], vec![1.0]))
+            .collect::<Vec<_>>();
+        assert_eq!(detect_arity_drift(&evals), None);
+        assert_eq!(detect_arity_drift(&[]), None);
+
+        evals[5].params.push(0.3);
+        evals[7].params.clear();
+        assert_eq!(detect_arity_drift(&evals), Some((5, 2, 3)));
+    }
+
+    fn timed(trial: TrialId, state: EvalState, start: f64, end: f64, value: f64) -> EvalRecord {
+        let mut e = eval(trial, state, vec![], vec![value]);
+        e.spans = vec![crate::Span::new(start, end)];
+        e
+    }
+
+    #[test]
+    fn cumulative_time_adds_each_trial_duration() {
+        let evals = vec![
+            timed(2, EvalState::Complete, 10.0, 14.0, 1.0),
+            timed(0, EvalState::Interim, 0.0, 1.0, 1.0),
+            timed(0, EvalState::Complete, 0.0, 2.0, 1.0),
+            timed(1, EvalState::Complete, 2.0, 2.5, 1.0),
+            timed(3, EvalState::Failed, 14.0, 20.0, 1.0),
+        ];
+        assert_eq!(
+            cumulative_time(&evals),
+            vec![
+                (0, Duration::from_secs(2)),
+                (1, Duration::from_millis(2500)),
+                (2, Duration::from_millis(6500)),
+            ]
+        );
+    }
+
+    #[test]
+    fn lint_infeasible_flags_finite_values() {
+        let evals = vec![
+            eval(0, EvalState::Infeasible, vec![], vec![0.5, f64::NAN]),
+            eval(1, EvalState::Infeasible, vec![], vec![f64::NAN, f64::NAN]),
+            eval(2, EvalState::Infeasible, vec![], vec![]),
+            eval(3, EvalState::Complete, vec![], vec![0.5, 0.5]),
+        ];
+        assert_eq!(lint_infeasible(&evals), vec![("s".to_owned(), 0)]);
+    }
+
+    #[test]
+    fn throughput_counts_completions_per_bucket() {
+        let evals = vec![
+            timed(0, EvalState::Complete, 0.0, 0.5, 1.0),
+            timed(1, EvalState::Complete, 0.0, 1.2, 1.0),
+            timed(2, EvalState::Interim, 0.0, 1.5, 1.0),
+            timed(2, EvalState::Complete, 0.0, 1.9, 1.0),
+            timed(3, EvalState::Failed, 0.0, 2.5, 1.0),
+            timed(4, EvalState::Complete, 0.0, 4.0, 1.0),
+        ];
+        assert_eq!(throughput(&evals, 1.0), vec![(0.0, 1), (1.0, 2), (4.0, 1)]);
+        assert_eq!(throughput(&evals, 10.0), vec![(0.0, 4)]);
+        assert!(throughput(&evals, 0.0).is_empty());
+
+        // Tiny buckets don't allocate one slot per (empty) bucket.
+        assert_eq!(throughput(&evals, 1e-9).len(), 4);
+    }
+
+    #[test]
+    fn best_within_time_ignores_trials_beyond_the_budget() {
+        let evals = vec![
+            timed(0, EvalState::Complete, 0.0, 3.0, 0.8),
+            timed(1, EvalState::Complete, 3.0, 5.0, 0.5),
+            timed(2, EvalState::Failed, 5.0, 6.0, 0.0),
+            timed(3, EvalState::Complete, 6.0, 16.0, 0.1),
+        ];
+        let best =
+            |budget| best_within_time(&evals, 0, Direction::Minimize, budget).map(|e| e.trial);
+        assert_eq!(best(f64::INFINITY), Some(3));
+        assert_eq!(best(10.0), Some(1));
+        assert_eq!(best(4.0), Some(0));
+        assert_eq!(best(1.0), None);
+    }
+
+    #[test]
+    fn scalar_values_mode_round_trips_single_objective_evals() {
+        let single = study(
+            Vec::new(),
+            vec![crate::ValueDef::new("loss", Direction::Minimize)],
+        );
+        let mut multi = study(
+            Vec::new(),
+            vec![
+                crate::ValueDef::new("loss", Direction::Minimize),
+                crate::ValueDef::new("acc", Direction::Maximize),
+            ],
+        );
+        multi.id = "m".to_owned();
+        let mut multi_eval = eval(0, EvalState::Complete, vec![2.0], vec![0.5, 0.9]);
+        multi_eval.study = "m".to_owned();
+        let records = vec![
+            Record::Study(single),
+            Record::Study(multi),
+            Record::Eval(eval(0, EvalState::Complete, vec![2.0], vec![0.5])),
+            Record::Eval(eval(1, EvalState::Failed, vec![2.0], vec![f64::NAN])),
+            Record::Eval(multi_eval),
+        ];
+
+        let output = write_lines(RecordWriter::new(Vec::new()).scalar_values(true), &records);
+        let text = String::from_utf8(output).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[2],
+            r#"{"eval":{"study":"s","trial":0,"state":"COMPLETE","spans":[],"params":[2.0],"value":0.5}}"#
+        );
+        assert_eq!(
+            lines[3],
+            r#"{"eval":{"study":"s","trial":1,"state":"FAILED","spans":[],"params":[2.0],"value":null}}"#
+        );
+        assert_eq!(
+            lines[4],
+            r#"{"eval":{"study":"m","trial":0,"state":"COMPLETE","spans":[],"params":[2.0],"values":[0.5,0.9]}}"#
+        );
+
+        let parsed = read_records(text.as_bytes())
+            .scalar_values(true)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(parsed.len(), records.len());
+        for (p, r) in parsed.iter().zip(&records) {
+            match (p, r) {
+                (Record::Eval(p), Record::Eval(r)) => assert!(p.approx_eq(r), "{:?}", p),
+                _ => assert_eq!(p, r),
+            }
+        }
+    }
+
+    #[test]
+    fn hit_rate_is_the_fraction_of_improving_trials() {
+        // Improvements: 5, 4, 3 and 2 (i.e., 4 out of 8).
+        let mut evals = completes(&[5.0, 6.0, 4.0, 4.0, 3.0, 7.0, 2.0, 2.5]);
+        evals.push(eval(8, EvalState::Failed, vec![], vec![0.0]));
+        assert_eq!(hit_rate(&evals, 0, Direction::Minimize), 0.5);
+        // Improvements: 5, 6 and 7.
+        assert_eq!(hit_rate(&evals, 0, Direction::Maximize), 3.0 / 8.0);
+        assert!(hit_rate(&[], 0, Direction::Minimize).is_nan());
+    }
+
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_errors_carry_the_record_context() {
+        let mut writer = RecordWriter::new(BrokenPipe);
+        let study = Record::Study(study(Vec::new(), Vec::new()));
+        let err = writer.write_record(&study).unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::Write { study, trial: None, source }
+                if study == "s" && source.kind() == std::io::ErrorKind::BrokenPipe
+        ));
+        assert!(err
+            .to_string()
+            .starts_with(r#"failed to write study record (study="s"): "#));
+
+        let mut writer = RecordWriter::new(BrokenPipe).compact_integers(true);
+        let eval = Record::Eval(eval(7, EvalState::Complete, vec![1.0], vec![0.5]));
+        let err = writer.write_record(&eval).unwrap_err();
+        assert!(matches!(&err, Error::Write { trial: Some(7), .. }));
+        assert!(err
+            .to_string()
+            .starts_with(r#"failed to write eval record (study="s", trial=7): "#));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn param_trajectory_follows_the_trial_records() {
+        let mut other = eval(0, EvalState::Interim, vec![9.0, 9.0], vec![]);
+        other.study = "t".to_owned();
+        let evals = vec![
+            eval(0, EvalState::Interim, vec![0.1, 32.0], vec![]),
+            eval(1, EvalState::Interim, vec![0.5, 32.0], vec![]),
+            other,
+            eval(0, EvalState::Interim, vec![0.1, 64.0], vec![]),
+            eval(0, EvalState::Complete, vec![0.1, 128.0], vec![]),
+        ];
+        assert_eq!(
+            param_trajectory(&evals, &"s".to_owned(), 0),
+            vec![vec![0.1, 32.0], vec![0.1, 64.0], vec![0.1, 128.0]]
+        );
+        assert!(param_trajectory(&evals, &"s".to_owned(), 2).is_empty());
+    }
+
+    #[test]
+    fn group_studies_by_group_collects_labeled_studies() {
+        let labeled = |id: &str, group: Option<&str>| {
+            let mut s = study(Vec::new(), Vec::new());
+            s.id = id.to_owned();
+            s.group = group.map(|g| g.to_owned());
+            s
+        };
+        let studies = vec![
+            labeled("a", Some("tpe")),
+            labeled("b", Some("random")),
+            labeled("c", None),
+            labeled("d", Some("tpe")),
+        ];
+        let groups = group_studies_by_group(&studies)
+            .into_iter()
+            .map(|(g, ss)| (g, ss.iter().map(|s| s.id.as_str()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            vec![
+                ("random".to_owned(), vec!["b"]),
+                ("tpe".to_owned(), vec!["a", "d"]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::f64::consts::PI;
+
+const COMPRESSION: f64 = 100.0;
+const BUFFER_SIZE: usize = 500;
+
+/// A t-digest which estimates quantiles of a value stream in bounded memory.
+///
+/// This is the merging variant of the t-digest with the `k1` (arcsine) scale function,
+/// so estimates are most accurate near the tails.
+#[derive(Debug, Default, Clone)]
+pub struct ValueDigest {
+    // Pairs of `(mean, weight)` sorted by mean.
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<f64>,
+    count: f64,
+    min: f64,
+    max: f64,
+}
+
+impl ValueDigest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `value` to the digest. Non-finite values (NaN and infinities) are ignored,
+    /// as in [`crate::StudyRecord::observed_value_range`], since they would poison the interpolated quantiles.
+    pub fn update(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        if self.count == 0.0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1.0;
+        self.buffer.push(value);
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.merge();
+        }
+    }
+
+    /// Estimates the `q`-quantile (`0 <= q <= 1`), or returns `None` if no value was added.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0.0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        if !self.buffer.is_empty() {
+            let mut digest = self.clone();
+            digest.merge();
+            return digest.quantile(q);
+        }
+
+        let target = q * self.count;
+        let (first_mean, first_weight) = self.centroids[0];
+        if target <= first_weight / 2.0 {
+            return Some(interpolate(
+                self.min,
+                first_mean,
+                target / (first_weight / 2.0),
+            ));
+        }
+        let mut center = first_weight / 2.0;
+        for w in self.centroids.windows(2) {
+            let ((m0, w0), (m1, w1)) = (w[0], w[1]);
+            let next = center + (w0 + w1) / 2.0;
+            if target <= next {
+                return Some(interpolate(m0, m1, (target - center) / (next - center)));
+            }
+            center = next;
+        }
+        let (last_mean, _) = self.centroids[self.centroids.len() - 1];
+        let rest = self.count - center;
+        Some(interpolate(last_mean, self.max, (target - center) / rest))
+    }
+
+    fn merge(&mut self) {
+        let mut items = std::mem::take(&mut self.centroids);
+        items.extend(self.buffer.drain(..).map(|v| (v, 1.0)));
+        items.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut merged: Vec<(f64, f64)> = Vec::with_capacity(items.len());
+        let mut weight_so_far = 0.0;
+        let mut limit = 0.0;
+        for (mean, weight) in items {
+            match merged.last_mut() {
+                Some((m, w)) if weight_so_far + weight <= limit => {
+                    *m += (mean - *m) * weight / (*w + weight);
+                    *w += weight;
+                }
+                _ => {
+                    // A new centroid may grow until its weight spans one unit of the scale function.
+                    limit = self.count * k_inv(k(weight_so_far / self.count) + 1.0);
+                    merged.push((mean, weight));
+                }
+            }
+            weight_so_far += weight;
+        }
+        self.centroids = merged;
+    }
+}
+
+fn k(q: f64) -> f64 {
+    COMPRESSION / (2.0 * PI) * (2.0 * q - 1.0).asin()
+}
+
+fn k_inv(k: f64) -> f64 {
+    let x = (k * 2.0 * PI / COMPRESSION).min(PI / 2.0);
+    (x.sin() + 1.0) / 2.0
+}
+
+fn interpolate(x: f64, y: f64, t: f64) -> f64 {
+    x + (y - x) * t.clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Exponentially distributed values from a fixed-seed LCG.
+    fn exponential_samples(n: usize) -> Vec<f64> {
+        let mut x = 42u64;
+        (0..n)
+            .map(|_| {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let u = (x >> 11) as f64 / (1u64 << 53) as f64;
+                -(1.0 - u).ln()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quantiles_match_exact_ones() {
+        let values = exponential_samples(20_000);
+        let mut digest = ValueDigest::new();
+        for &v in &values {
+            digest.update(v);
+        }
+        digest.update(f64::NAN);
+        let mut sorted = values;
+        sorted.sort_by(f64::total_cmp);
+
+        for &q in &[0.001, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 0.999] {
+            let estimate = digest.quantile(q).unwrap();
+            let exact = sorted[((q * sorted.len() as f64) as usize).min(sorted.len() - 1)];
+            // The rank of the estimate among the exact values should be close to `q`.
+            let rank = sorted.partition_point(|&v| v < estimate) as f64 / sorted.len() as f64;
+            let tolerance = if (0.05..=0.95).contains(&q) {
+                0.01
+            } else {
+                0.002
+            };
+            assert!(
+                (rank - q).abs() <= tolerance,
+                "q={}: estimate={} (rank {}), exact={}",
+                q,
+                estimate,
+                rank,
+                exact
+            );
+        }
+        assert_eq!(digest.quantile(0.0), Some(sorted[0]));
+        assert_eq!(digest.quantile(1.0), Some(sorted[sorted.len() - 1]));
+    }
+
+    #[test]
+    fn empty_digest_has_no_quantiles() {
+        let mut digest = ValueDigest::new();
+        assert_eq!(digest.quantile(0.5), None);
+        digest.update(f64::NAN);
+        assert_eq!(digest.quantile(0.0), None);
+        assert_eq!(digest.quantile(1.0), None);
+    }
+
+    #[test]
+    fn single_value_is_every_quantile() {
+        let mut digest = ValueDigest::new();
+        digest.update(3.5);
+        for &q in &[0.0, 0.25, 0.5, 0.75, 1.0] {
+            assert_eq!(digest.quantile(q), Some(3.5));
+        }
+        assert_eq!(digest.quantile(-0.1), None);
+        assert_eq!(digest.quantile(1.1), None);
+    }
+
+    #[test]
+    fn non_finite_values_are_ignored() {
+        let mut digest = ValueDigest::new();
+        digest.update(f64::INFINITY);
+        digest.update(1.0);
+        digest.update(2.0);
+        digest.update(f64::NEG_INFINITY);
+        digest.update(f64::NAN);
+        assert_eq!(digest.quantile(0.0), Some(1.0));
+        assert_eq!(digest.quantile(1.0), Some(2.0));
+        assert!(digest.quantile(0.5).unwrap().is_finite());
+    }
+
+    #[test]
+    fn extreme_quantiles_are_the_min_and_max() {
+        let mut digest = ValueDigest::new();
+        for i in 0..1234 {
+            digest.update(f64::from(i % 100) - 10.0);
+        }
+        assert_eq!(digest.quantile(0.0), Some(-10.0));
+        assert_eq!(digest.quantile(1.0), Some(89.0));
+        let median = digest.quantile(0.5).unwrap();
+        assert!((median - 39.5).abs() < 2.0, "{}", median);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{EvalRecord, StudyRecord, TrialId};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+/// Builds a table mapping each configuration (formatted by [`StudyRecord::format_config`]) to its results.
+///
+/// Each entry has the final objective (`function_value`), the wall-clock seconds (`cost`),
+/// all final values by name (`metrics`), and the first objective at each budget step (`budgets`).
+/// If several trials share a configuration, the later trial wins.
+pub fn to_table(study: &StudyRecord, evals: &[EvalRecord]) -> Value {
+    let mut trials: BTreeMap<TrialId, Vec<&EvalRecord>> = BTreeMap::new();
+    for eval in evals.iter().filter(|e| e.study == study.id) {
+        trials.entry(eval.trial).or_default().push(eval);
+    }
+
+    let mut table = Map::new();
+    for records in trials.values() {
+        let steps = records
+            .iter()
+            .filter(|e| e.state.is_interm() || e.state.is_complete())
+            .collect::<Vec<_>>();
+        let Some(last) = records
+            .iter()
+            .rev()
+            .find(|e| e.state.is_complete())
+            .or(records.last())
+        else {
+            continue;
+        };
+
+        let metrics = study
+            .values
+            .iter()
+            .zip(&last.values)
+            .map(|(d, &v)| (d.name.clone(), json!(v)))
+            .collect::<Map<_, _>>();
+        let budgets = steps
+            .iter()
+            .enumerate()
+            .map(|(i, e)| ((i + 1).to_string(), json!(e.values.first())))
+            .collect::<Map<_, _>>();
+        table.insert(
+            study.format_config(last),
+            json!({
+                "function_value": last.values.first(),
+                "cost": last.wall_clock().map(|d| d.as_secs_f64()),
+                "metrics": metrics,
+                "budgets": budgets,
+            }),
+        );
+    }
+    Value::Object(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, EvalState, ParamDef, Span, SpanDef, ValueDef};
+
+    #[test]
+    fn to_table_maps_configs_to_final_objectives() {
+        let mut study = crate::tests::study(
+            vec![
+                ParamDef::continuous("lr", 0.0, 1.0),
+                ParamDef::categorical("opt", vec!["sgd".to_owned(), "adam".to_owned()]),
+            ],
+            vec![
+                ValueDef::new("loss", Direction::Minimize),
+                ValueDef::new("acc", Direction::Maximize),
+            ],
+        );
+        study.spans = vec![SpanDef::new("train")];
+        let eval = |trial, state, values| EvalRecord {
+            study: "s".to_owned(),
+            trial,
+            state,
+            spans: vec![Span::new(0.0, 2.0)],
+            params: vec![0.01, 1.0],
+            values,
+        };
+        let mut other = eval(1, EvalState::Complete, vec![0.3, 0.7]);
+        other.params = vec![0.5, 0.0];
+        let evals = vec![
+            eval(0, EvalState::Interim, vec![0.4, 0.8]),
+            other,
+            eval(0, EvalState::Complete, vec![0.2, 0.9]),
+        ];
+
+        let table = to_table(&study, &evals);
+        assert_eq!(table.as_object().unwrap().len(), 2);
+        assert_eq!(
+            table["lr=0.01,opt=adam"],
+            json!({
+                "function_value": 0.2,
+                "cost": 2.0,
+                "metrics": {"loss": 0.2, "acc": 0.9},
+                "budgets": {"1": 0.4, "2": 0.2},
+            })
+        );
+        assert_eq!(table["lr=0.5,opt=sgd"]["function_value"], 0.3);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{EvalRecord, EvalState, ParamRange, Scale, StudyRecord, TrialId};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+pub fn to_optuna_json(study: &StudyRecord, evals: &[EvalRecord]) -> Value {
+    let mut trials: BTreeMap<TrialId, Vec<&EvalRecord>> = BTreeMap::new();
+    for eval in evals.iter().filter(|e| e.study == study.id) {
+        trials.entry(eval.trial).or_default().push(eval);
+    }
+
+    let distributions = study
+        .params
+        .iter()
+        .map(|p| (p.name.clone(), distribution(&p.range)))
+        .collect::<Map<_, _>>();
+    let trials = trials
+        .into_iter()
+        .filter_map(|(number, records)| {
+            let last = records.last()?;
+            let params = study
+                .params
+                .iter()
+                .zip(&last.params)
+                .map(|(p, &v)| {
+                    let v = match p.range.choice(v) {
+                        Some(c) => json!(c),
+                        None if is_int(&p.range) && v.is_finite() => json!(v as i64),
+                        None => json!(v),
+                    };
+                    (p.name.clone(), v)
+                })
+                .collect::<Map<_, _>>();
+            let intermediate_values = records
+                .iter()
+                .filter(|e| e.state.is_interm())
+                .enumerate()
+                .map(|(step, e)| (step.to_string(), json!(e.values.first())))
+                .collect::<Map<_, _>>();
+            let values = if last.state.is_complete() {
+                json!(last.values)
+            } else {
+                Value::Null
+            };
+            Some(json!({
+                "number": number,
+                "state": state(last.state),
+                "params": params,
+                "distributions": distributions,
+                "values": values,
+                "intermediate_values": intermediate_values,
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    let directions = study
+        .values
+        .iter()
+        .map(|v| {
+            if v.direction.is_minimize() {
+                "MINIMIZE"
+            } else {
+                "MAXIMIZE"
+            }
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "study_name": study.id,
+        "directions": directions,
+        "user_attrs": study.attrs,
+        "trials": trials,
+    })
+}
+
+fn state(state: EvalState) -> &'static str {
+    match state {
+        EvalState::Complete => "COMPLETE",
+        EvalState::Interim => "PRUNED",
+        EvalState::Failed | EvalState::Infeasible => "FAIL",
+    }
+}
+
+fn is_int(range: &ParamRange) -> bool {
+    match range {
+        ParamRange::Numerical {
+            min,
+            max,
+            step: Some(step),
+            scale,
+        } => {
+            // Optuna only supports log-scaled integers with a step of one.
+            min.fract() == 0.0
+                && max.fract() == 0.0
+                && step.fract() == 0.0
+                && (*scale == Scale::Linear || *step == 1.0)
+        }
+        _ => false,
+    }
+}
+
+fn distribution(range: &ParamRange) -> Value {
+    match range {
+        ParamRange::Numerical {
+            min,
+            max,
+            step: Some(step),
+            scale,
+        } if is_int(range) => json!({
+            "name": "IntDistribution",
+            "attributes": {"low": *min as i64, "high": *max as i64, "step": *step as i64, "log": *scale == Scale::Log},
+        }),
+        ParamRange::Numerical {
+            min,
+            max,
+            step,
+            scale,
+        } => {
+            // Optuna rejects float distributions with both a step and the log scale.
+            let log = *scale == Scale::Log;
+            json!({
+                "name": "FloatDistribution",
+                "attributes": {"low": min, "high": max, "step": if log { None } else { *step }, "log": log},
+            })
+        }
+        ParamRange::Categorical { choices } => json!({
+            "name": "CategoricalDistribution",
+            "attributes": {"choices": choices},
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, ParamDef, ValueDef};
+
+    #[test]
+    fn to_optuna_json_maps_distributions_and_trials() {
+        let study = crate::tests::study(
+            vec![
+                ParamDef::categorical("opt", vec!["sgd".to_owned(), "adam".to_owned()]),
+                ParamDef::discrete("layers", 1.0, 4.0, 1.0),
+            ],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let eval = |state, values| EvalRecord {
+            study: "s".to_owned(),
+            trial: 0,
+            state,
+            spans: Vec::new(),
+            params: vec![1.0, 3.0],
+            values,
+        };
+        let evals = vec![
+            eval(EvalState::Interim, vec![0.8]),
+            eval(EvalState::Complete, vec![0.5]),
+        ];
+
+        let json = to_optuna_json(&study, &evals);
+        assert_eq!(json["directions"], json!(["MINIMIZE"]));
+        let trial = &json["trials"][0];
+        assert_eq!(
+            trial["distributions"]["opt"],
+            json!({
+                "name": "CategoricalDistribution",
+                "attributes": {"choices": ["sgd", "adam"]},
+            })
+        );
+        assert_eq!(trial["distributions"]["layers"]["name"], "IntDistribution");
+        assert_eq!(trial["number"], 0);
+        assert_eq!(trial["state"], "COMPLETE");
+        assert_eq!(trial["params"], json!({"opt": "adam", "layers": 3}));
+        assert_eq!(trial["values"], json!([0.5]));
+        assert_eq!(trial["intermediate_values"], json!({"0": 0.8}));
+    }
+
+    #[test]
+    fn log_ranges_map_to_valid_distributions() {
+        let log = |min, max, step| ParamRange::Numerical {
+            min,
+            max,
+            step,
+            scale: Scale::Log,
+        };
+        assert_eq!(
+            distribution(&log(1.0, 1024.0, Some(1.0))),
+            json!({
+                "name": "IntDistribution",
+                "attributes": {"low": 1, "high": 1024, "step": 1, "log": true},
+            })
+        );
+        assert_eq!(
+            distribution(&log(1e-5, 1.0, None)),
+            json!({
+                "name": "FloatDistribution",
+                "attributes": {"low": 1e-5, "high": 1.0, "step": null, "log": true},
+            })
+        );
+        // Optuna supports neither stepped float distributions nor integer ones with other steps on the log scale.
+        for range in [log(0.5, 4.0, Some(0.5)), log(2.0, 64.0, Some(2.0))] {
+            let d = distribution(&range);
+            assert_eq!(d["name"], "FloatDistribution");
+            assert_eq!(d["attributes"]["step"], Value::Null);
+            assert_eq!(d["attributes"]["log"], true);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
+use std::fmt;
 use std::time::Duration;
 
 pub mod io;
@@ -7,34 +9,75 @@ pub mod io;
 pub type StudyId = String;
 pub type TrialId = u32;
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+/// The well-known study attribute naming the param that holds the multi-fidelity rung (see [`StudyRecord::rung_of`]).
+pub const RUNG_PARAM_ATTR: &str = "rung-param";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Span {
+    #[serde(with = "nullable_f64")]
     pub start: f64,
+
+    #[serde(with = "nullable_f64")]
     pub end: f64,
+
+    /// Extra fields attached by producers (e.g., `"name"`), kept for round-tripping.
+    ///
+    /// Values are kept as JSON rather than strings so that numeric or nested annotations round-trip as well.
+    #[serde(
+        flatten,
+        default,
+        deserialize_with = "deserialize_span_meta",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub meta: Option<BTreeMap<String, serde_json::Value>>,
 }
 
 impl Span {
     pub fn new(start: f64, end: f64) -> Self {
-        Self { start, end }
+        Self {
+            start,
+            end,
+            meta: None,
+        }
     }
 }
 
 impl Span {
     // TODO: remove
-    pub fn start(self) -> Duration {
+    pub fn start(&self) -> Duration {
         Duration::from_secs_f64(self.start)
     }
 
-    pub fn end(self) -> Duration {
+    pub fn end(&self) -> Duration {
         Duration::from_secs_f64(self.end)
     }
 
-    pub fn duration(self) -> Duration {
+    pub fn duration(&self) -> Duration {
         self.end() - self.start()
     }
+
+    /// Like [`Span::duration`], but returns `None` instead of panicking on non-finite or reversed endpoints.
+    pub fn checked_duration(&self) -> Option<Duration> {
+        let d = self.end - self.start;
+        if d.is_finite() && d >= 0.0 {
+            Some(Duration::from_secs_f64(d))
+        } else {
+            None
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn deserialize_span_meta<'de, D>(
+    deserializer: D,
+) -> Result<Option<BTreeMap<String, serde_json::Value>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let meta: BTreeMap<String, serde_json::Value> = Deserialize::deserialize(deserializer)?;
+    Ok(if meta.is_empty() { None } else { Some(meta) })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SpanDef {
     pub name: String,
 }
@@ -82,9 +125,10 @@ impl ParamDef {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum Scale {
+    #[default]
     Linear,
     Log,
 }
@@ -93,11 +137,24 @@ impl Scale {
     fn is_default(&self) -> bool {
         *self == Self::Linear
     }
-}
 
-impl Default for Scale {
-    fn default() -> Self {
-        Self::Linear
+    /// Maps `x` into the space of this scale.
+    ///
+    /// For `Log`, non-positive inputs yield NaN.
+    pub fn apply(self, x: f64) -> f64 {
+        match self {
+            Self::Linear => x,
+            Self::Log if x > 0.0 => x.ln(),
+            Self::Log => f64::NAN,
+        }
+    }
+
+    /// The inverse of [`Scale::apply`].
+    pub fn invert(self, x: f64) -> f64 {
+        match self {
+            Self::Linear => x,
+            Self::Log => x.exp(),
+        }
     }
 }
 
@@ -169,14 +226,102 @@ impl ParamRange {
             Self::Categorical { .. } => Scale::Linear,
         }
     }
+
+    pub fn cardinality(&self) -> Option<usize> {
+        match self {
+            Self::Numerical {
+                min,
+                max,
+                step: Some(step),
+                ..
+            } if *step > 0.0 && min <= max => {
+                // A small tolerance absorbs rounding errors such as `(1.0 - 0.0) / 0.1 < 10.0`.
+                let n = ((max - min) / step + 1e-9).floor();
+                if n < usize::MAX as f64 {
+                    (n as usize).checked_add(1)
+                } else {
+                    None
+                }
+            }
+            Self::Numerical { .. } => None,
+            Self::Categorical { choices } => Some(choices.len()),
+        }
+    }
+
+    pub fn grid(&self) -> Option<Vec<f64>> {
+        let n = self.cardinality()?;
+        Some(match self {
+            Self::Numerical { min, step, .. } => {
+                let step = step.unwrap_or(0.0);
+                (0..n).map(|i| min + step * i as f64).collect()
+            }
+            Self::Categorical { .. } => (0..n).map(|i| i as f64).collect(),
+        })
+    }
+
+    pub fn choice_index_map(&self) -> Option<BTreeMap<&str, usize>> {
+        match self {
+            Self::Numerical { .. } => None,
+            Self::Categorical { choices } => Some(
+                // Reversed so that the first occurrence wins if choices are duplicated.
+                choices
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .map(|(i, c)| (c.as_str(), i))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn choice(&self, v: f64) -> Option<&str> {
+        match self {
+            Self::Numerical { .. } => None,
+            Self::Categorical { choices } => {
+                if v >= 0.0 && v.fract() == 0.0 {
+                    choices.get(v as usize).map(|c| c.as_str())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn decode(&self, v: f64) -> ParamValue {
+        if let Some(c) = self.choice(v) {
+            ParamValue::Categorical(c.to_owned())
+        } else {
+            ParamValue::Numerical(v)
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Numerical(f64),
+    Categorical(String),
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Numerical(v) => write!(f, "{}", v),
+            Self::Categorical(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ValueDef {
     pub name: String,
     #[serde(default, skip_serializing_if = "ValueRange::is_default")]
     pub range: ValueRange,
     pub direction: Direction,
+
+    /// If `true`, this value is a constraint which is satisfied when `<= 0`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub constraint: bool,
 }
 
 impl ValueDef {
@@ -185,11 +330,19 @@ impl ValueDef {
             name: name.into(),
             range: ValueRange::default(),
             direction,
+            constraint: false,
+        }
+    }
+
+    pub fn new_constraint(name: impl Into<String>) -> Self {
+        Self {
+            constraint: true,
+            ..Self::new(name, Direction::Minimize)
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ValueRange {
     #[serde(default = "neg_infinity", skip_serializing_if = "is_neg_infinity")]
     pub min: f64,
@@ -200,33 +353,37 @@ pub struct ValueRange {
 
 impl ValueRange {
     fn is_default(&self) -> bool {
-        self.min == std::f64::NEG_INFINITY && self.max == std::f64::INFINITY
+        self.min == f64::NEG_INFINITY && self.max == f64::INFINITY
     }
 }
 
 impl Default for ValueRange {
     fn default() -> Self {
         Self {
-            min: std::f64::NEG_INFINITY,
-            max: std::f64::INFINITY,
+            min: f64::NEG_INFINITY,
+            max: f64::INFINITY,
         }
     }
 }
 
+fn is_false(v: &bool) -> bool {
+    !*v
+}
+
 fn neg_infinity() -> f64 {
-    std::f64::NEG_INFINITY
+    f64::NEG_INFINITY
 }
 
 fn is_neg_infinity(v: &f64) -> bool {
-    *v == std::f64::NEG_INFINITY
+    *v == f64::NEG_INFINITY
 }
 
 fn infinity() -> f64 {
-    std::f64::INFINITY
+    f64::INFINITY
 }
 
 fn is_infinity(v: &f64) -> bool {
-    *v == std::f64::INFINITY
+    *v == f64::INFINITY
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -252,9 +409,17 @@ impl Direction {
     pub const fn is_maximize(self) -> bool {
         matches!(self, Self::Maximize)
     }
+
+    pub fn worst_sentinel(self) -> f64 {
+        if self.is_minimize() {
+            f64::INFINITY
+        } else {
+            f64::NEG_INFINITY
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum Record {
     Study(StudyRecord),
@@ -262,10 +427,35 @@ pub enum Record {
     // TODO: StudyEnd
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Record {
+    /// Validates a study record by itself, or an eval record against the study it refers to.
+    pub fn validate(
+        &self,
+        known_studies: &BTreeMap<StudyId, StudyRecord>,
+    ) -> Result<(), ValidationError> {
+        match self {
+            Self::Study(study) => study.validate(),
+            Self::Eval(eval) => {
+                let study = known_studies
+                    .get(&eval.study)
+                    .ok_or_else(|| ValidationError::UnknownStudy(eval.study.clone()))?;
+                study.validate_eval(eval)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct StudyRecord {
     pub id: StudyId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// A label shared by studies of the same experiment (e.g., runs with different seeds).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
     #[serde(default)]
     pub attrs: BTreeMap<String, String>,
     pub spans: Vec<SpanDef>,
@@ -273,6 +463,587 @@ pub struct StudyRecord {
     pub values: Vec<ValueDef>,
 }
 
+impl StudyRecord {
+    /// Returns a copy of this study without attributes or labels (name, description and group),
+    /// so that studies with the same schema compare equal.
+    pub fn schema_only(&self) -> StudyRecord {
+        StudyRecord {
+            name: None,
+            description: None,
+            group: None,
+            attrs: BTreeMap::new(),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn with_group(mut self, group: impl Into<String>) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    pub fn with_attr(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.set_attr(key, value);
+        self
+    }
+
+    pub fn set_attr(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.attrs.insert(key.into(), value.into())
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if let Some(name) = find_duplicate(self.spans.iter().map(|s| s.name.as_str())) {
+            return Err(ValidationError::DuplicateSpan(name.to_owned()));
+        }
+        if let Some(name) = find_duplicate(self.params.iter().map(|p| p.name.as_str())) {
+            return Err(ValidationError::DuplicateParam(name.to_owned()));
+        }
+        if let Some(name) = find_duplicate(self.values.iter().map(|v| v.name.as_str())) {
+            return Err(ValidationError::DuplicateValue(name.to_owned()));
+        }
+        if let Some(p) = self.params.iter().find(|p| {
+            let order = p.range.min().partial_cmp(&p.range.max());
+            order.is_none_or(Ordering::is_gt)
+        }) {
+            return Err(ValidationError::InvalidRange(p.name.clone()));
+        }
+        Ok(())
+    }
+
+    /// Checks that `eval` belongs to this study and matches its schema.
+    ///
+    /// Spans with a non-finite endpoint are regarded as missing, but others must not end before they start.
+    pub fn validate_eval(&self, eval: &EvalRecord) -> Result<(), ValidationError> {
+        if eval.study != self.id {
+            return Err(ValidationError::UnknownStudy(eval.study.clone()));
+        }
+        for (field, expected, actual) in [
+            ("spans", self.spans.len(), eval.spans.len()),
+            ("params", self.params.len(), eval.params.len()),
+            ("values", self.values.len(), eval.values.len()),
+        ] {
+            if expected != actual {
+                return Err(ValidationError::ArityMismatch {
+                    trial: eval.trial,
+                    field,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        if let Some(index) = eval
+            .spans
+            .iter()
+            .position(|s| s.start.is_finite() && s.end.is_finite() && s.start > s.end)
+        {
+            return Err(ValidationError::InvalidSpan {
+                trial: eval.trial,
+                index,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that `other` has the same params and the same value names, directions and roles,
+    /// so that their results can be aggregated. Ids and attributes are ignored.
+    pub fn assert_comparable(&self, other: &StudyRecord) -> Result<(), CompareError> {
+        if self.params.len() != other.params.len() {
+            return Err(CompareError::ParamCountMismatch {
+                left: self.params.len(),
+                right: other.params.len(),
+            });
+        }
+        if let Some((index, p)) = self
+            .params
+            .iter()
+            .enumerate()
+            .find(|(i, p)| **p != other.params[*i])
+        {
+            return Err(CompareError::ParamMismatch {
+                index,
+                name: p.name.clone(),
+            });
+        }
+        if self.values.len() != other.values.len() {
+            return Err(CompareError::ValueCountMismatch {
+                left: self.values.len(),
+                right: other.values.len(),
+            });
+        }
+        for (l, r) in self.values.iter().zip(&other.values) {
+            if l.name != r.name {
+                return Err(CompareError::ValueNameMismatch {
+                    left: l.name.clone(),
+                    right: r.name.clone(),
+                });
+            }
+            if l.direction != r.direction || l.constraint != r.constraint {
+                return Err(CompareError::ValueKindMismatch {
+                    name: l.name.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `false` if `eval` is infeasible or violates (or lacks) any constraint value.
+    pub fn is_feasible(&self, eval: &EvalRecord) -> bool {
+        !eval.state.is_infeasible()
+            && self
+                .values
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| v.constraint)
+                .all(|(i, _)| eval.values.get(i).is_some_and(|&v| v <= 0.0))
+    }
+
+    /// Returns the number of configurations yielded by [`StudyRecord::enumerate_grid`].
+    pub fn grid_size(&self) -> Option<usize> {
+        self.params
+            .iter()
+            .try_fold(1usize, |acc, p| acc.checked_mul(p.range.cardinality()?))
+    }
+
+    /// Yields every configuration of a fully discrete study, or returns `None` if any param is continuous.
+    ///
+    /// Configurations are computed lazily, so even a huge grid can be sampled with `Iterator::take`,
+    /// but check [`StudyRecord::grid_size`] before exhausting it.
+    pub fn enumerate_grid(&self) -> Option<impl Iterator<Item = Vec<f64>>> {
+        let ranges = self
+            .params
+            .iter()
+            .map(|p| {
+                let n = p.range.cardinality()?;
+                // Categorical coordinates are choice indices.
+                Some(match p.range {
+                    ParamRange::Numerical { min, step, .. } => (min, step.unwrap_or(0.0), n),
+                    ParamRange::Categorical { .. } => (0.0, 1.0, n),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let mut indices = if ranges.iter().any(|&(_, _, n)| n == 0) {
+            None
+        } else {
+            Some(vec![0; ranges.len()])
+        };
+        Some(std::iter::from_fn(move || {
+            let current = indices.as_mut()?;
+            let config = current
+                .iter()
+                .zip(&ranges)
+                .map(|(&i, &(min, step, _))| min + step * i as f64)
+                .collect::<Vec<_>>();
+            let mut done = true;
+            for (i, &(_, _, n)) in current.iter_mut().zip(&ranges).rev() {
+                *i += 1;
+                if *i < n {
+                    done = false;
+                    break;
+                }
+                *i = 0;
+            }
+            if done {
+                indices = None;
+            }
+            Some(config)
+        }))
+    }
+
+    /// Appends a value computed by `f` from the existing values of each eval.
+    ///
+    /// Evals received later can be extended by [`io::apply_derived`] with the same `f`.
+    pub fn add_derived_value<F>(
+        &mut self,
+        name: impl Into<String>,
+        direction: Direction,
+        evals: &mut [EvalRecord],
+        f: F,
+    ) where
+        F: Fn(&[f64]) -> f64,
+    {
+        self.values.push(ValueDef::new(name, direction));
+        io::apply_derived(self, evals, f);
+    }
+
+    /// Renames params by `f`, leaving them untouched if the new names aren't unique.
+    pub fn map_param_names<F>(&mut self, f: F) -> Result<(), DuplicateName>
+    where
+        F: FnMut(&str) -> String,
+    {
+        let names = rename_all(self.params.iter().map(|p| p.name.as_str()), f)?;
+        for (p, name) in self.params.iter_mut().zip(names) {
+            p.name = name;
+        }
+        Ok(())
+    }
+
+    /// Renames values by `f`, leaving them untouched if the new names aren't unique.
+    pub fn map_value_names<F>(&mut self, f: F) -> Result<(), DuplicateName>
+    where
+        F: FnMut(&str) -> String,
+    {
+        let names = rename_all(self.values.iter().map(|v| v.name.as_str()), f)?;
+        for (v, name) in self.values.iter_mut().zip(names) {
+            v.name = name;
+        }
+        Ok(())
+    }
+
+    fn evals<'a>(&'a self, evals: &'a [EvalRecord]) -> impl 'a + Iterator<Item = &'a EvalRecord> {
+        evals.iter().filter(move |eval| eval.study == self.id)
+    }
+
+    pub fn observed_value_range(
+        &self,
+        evals: &[EvalRecord],
+        value_index: usize,
+    ) -> Option<ValueRange> {
+        let mut range: Option<ValueRange> = None;
+        for v in self
+            .evals(evals)
+            .filter(|eval| eval.state.is_complete())
+            .filter_map(|eval| eval.values.get(value_index).copied())
+            .filter(|v| v.is_finite())
+        {
+            let r = range.get_or_insert(ValueRange { min: v, max: v });
+            r.min = r.min.min(v);
+            r.max = r.max.max(v);
+        }
+        range
+    }
+
+    /// Returns the multi-fidelity rung of `eval`.
+    ///
+    /// The rung is read from the param named by the [`RUNG_PARAM_ATTR`] (`"rung-param"`) attribute of this study,
+    /// so this returns `None` if the attribute is absent (as in logs of producers that don't set it)
+    /// or the value isn't a non-negative integer.
+    ///
+    /// Note that this doesn't agree with [`io::group_by_rung`], which counts interim records instead,
+    /// so the rung returned here may differ from the group key of the same eval there.
+    pub fn rung_of(&self, eval: &EvalRecord) -> Option<usize> {
+        let name = self.attrs.get(RUNG_PARAM_ATTR)?;
+        let index = self.params.iter().position(|p| p.name == *name)?;
+        let v = *eval.params.get(index)?;
+        if v >= 0.0 && v.fract() == 0.0 {
+            Some(v as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn objective_index(&self, name: &str) -> Result<usize, UnknownValue> {
+        self.values
+            .iter()
+            .position(|v| v.name == name)
+            .ok_or_else(|| UnknownValue {
+                name: name.to_owned(),
+                available: self.values.iter().map(|v| v.name.clone()).collect(),
+            })
+    }
+
+    pub fn categorical_matrix(
+        &self,
+        evals: &[EvalRecord],
+    ) -> BTreeMap<String, Vec<Option<String>>> {
+        let completes = self
+            .evals(evals)
+            .filter(|e| e.state.is_complete())
+            .collect::<Vec<_>>();
+        self.params
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| matches!(p.range, ParamRange::Categorical { .. }))
+            .map(|(i, p)| {
+                let column = completes
+                    .iter()
+                    .map(|e| {
+                        e.params
+                            .get(i)
+                            .and_then(|&v| p.range.choice(v))
+                            .map(|c| c.to_owned())
+                    })
+                    .collect();
+                (p.name.clone(), column)
+            })
+            .collect()
+    }
+
+    /// Returns `(bin center, best value)` pairs over the range of a numerical param.
+    ///
+    /// Bins are equally spaced in the param's scale, and empty bins are omitted.
+    /// Returns `None` if the param is unknown or categorical, or if `bins` is zero.
+    pub fn marginal_best(
+        &self,
+        evals: &[EvalRecord],
+        param_name: &str,
+        value_index: usize,
+        direction: Direction,
+        bins: usize,
+    ) -> Option<Vec<(f64, f64)>> {
+        let index = self.params.iter().position(|p| p.name == param_name)?;
+        let range = &self.params[index].range;
+        if bins == 0 || matches!(range, ParamRange::Categorical { .. }) {
+            return None;
+        }
+
+        let scale = range.scale();
+        let lo = scale.apply(range.min());
+        let hi = scale.apply(range.max());
+        let width = (hi - lo) / bins as f64;
+        let mut best = vec![None; bins];
+        for eval in self.evals(evals).filter(|e| e.state.is_complete()) {
+            let (Some(&x), Some(&v)) = (eval.params.get(index), eval.values.get(value_index))
+            else {
+                continue;
+            };
+            let x = scale.apply(x);
+            if x.is_nan() || v.is_nan() || x < lo || x > hi {
+                continue;
+            }
+            let bin = if width > 0.0 {
+                (((x - lo) / width) as usize).min(bins - 1)
+            } else {
+                0
+            };
+            let b: &mut Option<f64> = &mut best[bin];
+            *b = Some(b.map_or(v, |b| direction.better(b, v)));
+        }
+        Some(
+            best.into_iter()
+                .enumerate()
+                .filter_map(|(i, b)| {
+                    let center = scale.invert(lo + width * (i as f64 + 0.5));
+                    Some((center, b?))
+                })
+                .collect(),
+        )
+    }
+
+    /// Counts complete evals per tuple of categorical choices (in schema order).
+    ///
+    /// Evals with a missing or invalid choice are not counted.
+    pub fn categorical_combinations(&self, evals: &[EvalRecord]) -> BTreeMap<Vec<String>, usize> {
+        let mut counts = BTreeMap::new();
+        for eval in self.evals(evals).filter(|e| e.state.is_complete()) {
+            let combination = self
+                .params
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| matches!(p.range, ParamRange::Categorical { .. }))
+                .map(|(i, p)| {
+                    let c = p.range.choice(*eval.params.get(i)?)?;
+                    Some(c.to_owned())
+                })
+                .collect::<Option<Vec<_>>>();
+            if let Some(combination) = combination {
+                *counts.entry(combination).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Returns the absolute Spearman correlation between each numerical param and the final objective.
+    ///
+    /// Only complete trials that reached the full fidelity (i.e., the most interim records) are used,
+    /// so that pruned trials don't bias the result.
+    /// The score is zero if it can't be computed (e.g., fewer than two trials or a constant column).
+    pub fn fidelity_importance(
+        &self,
+        evals: &[EvalRecord],
+        value_index: usize,
+    ) -> Vec<(String, f64)> {
+        let mut trials: BTreeMap<TrialId, (usize, Option<&EvalRecord>)> = BTreeMap::new();
+        for eval in self.evals(evals) {
+            let entry = trials.entry(eval.trial).or_insert((0, None));
+            if eval.state.is_interm() {
+                entry.0 += 1;
+            } else if eval.state.is_complete() {
+                entry.1 = Some(eval);
+            }
+        }
+        let max_steps = trials
+            .values()
+            .filter(|(_, e)| e.is_some())
+            .map(|(n, _)| *n)
+            .max()
+            .unwrap_or(0);
+        let finals = trials
+            .into_values()
+            .filter(|(n, _)| *n == max_steps)
+            .filter_map(|(_, e)| e)
+            .collect::<Vec<_>>();
+
+        self.params
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| matches!(p.range, ParamRange::Numerical { .. }))
+            .map(|(i, p)| {
+                let (xs, ys): (Vec<f64>, Vec<f64>) = finals
+                    .iter()
+                    .filter_map(|e| Some((*e.params.get(i)?, *e.values.get(value_index)?)))
+                    .filter(|(x, y)| !x.is_nan() && !y.is_nan())
+                    .unzip();
+                let rho = spearman(&xs, &ys);
+                (p.name.clone(), if rho.is_nan() { 0.0 } else { rho.abs() })
+            })
+            .collect()
+    }
+
+    /// Transposes the evals of this study into columns keyed by param and value names.
+    ///
+    /// Missing params and values are filled with NaN.
+    pub fn to_columns(&self, evals: &[EvalRecord]) -> io::Columns {
+        let mut columns = io::Columns::default();
+        let mut params = vec![Vec::new(); self.params.len()];
+        let mut values = vec![Vec::new(); self.values.len()];
+        for eval in self.evals(evals) {
+            columns.trial.push(eval.trial);
+            columns.state.push(eval.state);
+            for (i, column) in params.iter_mut().enumerate() {
+                column.push(eval.params.get(i).copied().unwrap_or(f64::NAN));
+            }
+            for (i, column) in values.iter_mut().enumerate() {
+                column.push(eval.values.get(i).copied().unwrap_or(f64::NAN));
+            }
+        }
+        columns.params = self
+            .params
+            .iter()
+            .map(|p| p.name.clone())
+            .zip(params)
+            .collect();
+        columns.values = self
+            .values
+            .iter()
+            .map(|v| v.name.clone())
+            .zip(values)
+            .collect();
+        columns
+    }
+
+    /// Returns the number of params that took more than one distinct value across complete evals.
+    pub fn effective_dimensions(&self, evals: &[EvalRecord]) -> usize {
+        (0..self.params.len())
+            .filter(|&i| {
+                let mut observed = self
+                    .evals(evals)
+                    .filter(|e| e.state.is_complete())
+                    .filter_map(|e| e.params.get(i).copied())
+                    .filter(|v| !v.is_nan());
+                observed
+                    .next()
+                    .is_some_and(|first| observed.any(|v| v != first))
+            })
+            .count()
+    }
+
+    /// Formats the params of `eval` as `name=value` pairs separated by commas (e.g., `lr=0.01,opt=adam`).
+    pub fn format_config(&self, eval: &EvalRecord) -> String {
+        self.params
+            .iter()
+            .zip(&eval.params)
+            .map(|(p, &v)| format!("{}={}", p.name, p.range.decode(v)))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Combines the objective values (i.e., non-constraint values) of `eval` into a single score to be minimized.
+    ///
+    /// The score is the weighted sum of the objectives, where those to be maximized are negated.
+    /// `weights` must have one finite, non-negative weight per objective.
+    pub fn scalarize(&self, eval: &EvalRecord, weights: &[f64]) -> Result<f64, ScalarizeError> {
+        let objectives = self
+            .values
+            .iter()
+            .zip(
+                eval.values
+                    .iter()
+                    .copied()
+                    .chain(std::iter::repeat(f64::NAN)),
+            )
+            .filter(|(d, _)| !d.constraint)
+            .collect::<Vec<_>>();
+        if objectives.len() != weights.len() {
+            return Err(ScalarizeError::WeightCountMismatch {
+                expected: objectives.len(),
+                actual: weights.len(),
+            });
+        }
+        if let Some((index, &weight)) = weights
+            .iter()
+            .enumerate()
+            .find(|(_, w)| !w.is_finite() || **w < 0.0)
+        {
+            return Err(ScalarizeError::InvalidWeight { index, weight });
+        }
+
+        Ok(objectives
+            .iter()
+            .zip(weights)
+            .map(|((d, v), w)| {
+                if d.direction.is_minimize() {
+                    w * v
+                } else {
+                    -w * v
+                }
+            })
+            .sum())
+    }
+
+    /// Splits the params of `eval` into numerical values and categorical indices, each in schema order.
+    ///
+    /// Missing numerical values are NaN, and missing or invalid choices are mapped to the number of choices.
+    pub fn split_params(&self, eval: &EvalRecord) -> (Vec<f64>, Vec<usize>) {
+        let mut numericals = Vec::new();
+        let mut categoricals = Vec::new();
+        for (i, p) in self.params.iter().enumerate() {
+            let v = eval.params.get(i).copied().unwrap_or(f64::NAN);
+            match &p.range {
+                ParamRange::Numerical { .. } => numericals.push(v),
+                ParamRange::Categorical { choices } => {
+                    let index = if p.range.choice(v).is_some() {
+                        v as usize
+                    } else {
+                        choices.len()
+                    };
+                    categoricals.push(index);
+                }
+            }
+        }
+        (numericals, categoricals)
+    }
+
+    pub fn flatten(&self, eval: &EvalRecord) -> io::FlatEval {
+        io::FlatEval {
+            study: eval.study.clone(),
+            trial: eval.trial,
+            state: eval.state,
+            params: self
+                .params
+                .iter()
+                .zip(&eval.params)
+                .map(|(p, &v)| (p.name.clone(), p.range.decode(v)))
+                .collect(),
+            values: self
+                .values
+                .iter()
+                .zip(&eval.values)
+                .map(|(d, &v)| (d.name.clone(), v))
+                .collect(),
+            wall_clock: eval.wall_clock(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum EvalState {
@@ -298,9 +1069,232 @@ impl EvalState {
     pub const fn is_infeasible(self) -> bool {
         matches!(self, Self::Infeasible)
     }
+
+    /// Returns how final this state is. Terminal states rank above `Interim`, and `Complete` ranks highest.
+    ///
+    /// This also defines the `Ord` of `EvalState`.
+    pub const fn priority(self) -> u8 {
+        match self {
+            Self::Interim => 0,
+            Self::Failed => 1,
+            Self::Infeasible => 2,
+            Self::Complete => 3,
+        }
+    }
+}
+
+impl PartialOrd for EvalState {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EvalState {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority().cmp(&other.priority())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownValue {
+    pub name: String,
+    pub available: Vec<String>,
+}
+
+impl fmt::Display for UnknownValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown value {:?} (available: {:?})",
+            self.name, self.available
+        )
+    }
+}
+
+impl std::error::Error for UnknownValue {}
+
+fn spearman(xs: &[f64], ys: &[f64]) -> f64 {
+    fn ranks(xs: &[f64]) -> Vec<f64> {
+        let mut indices = (0..xs.len()).collect::<Vec<_>>();
+        indices.sort_by(|&a, &b| xs[a].total_cmp(&xs[b]));
+        let mut ranks = vec![0.0; xs.len()];
+        let mut i = 0;
+        while i < indices.len() {
+            let mut j = i;
+            while j + 1 < indices.len() && xs[indices[j + 1]] == xs[indices[i]] {
+                j += 1;
+            }
+            let rank = (i + j) as f64 / 2.0;
+            for &k in &indices[i..=j] {
+                ranks[k] = rank;
+            }
+            i = j + 1;
+        }
+        ranks
+    }
+
+    let n = xs.len() as f64;
+    let (rx, ry) = (ranks(xs), ranks(ys));
+    let (mx, my) = (rx.iter().sum::<f64>() / n, ry.iter().sum::<f64>() / n);
+    let cov = rx
+        .iter()
+        .zip(&ry)
+        .map(|(x, y)| (x - mx) * (y - my))
+        .sum::<f64>();
+    let vx = rx.iter().map(|x| (x - mx).powi(2)).sum::<f64>();
+    let vy = ry.iter().map(|y| (y - my).powi(2)).sum::<f64>();
+    cov / (vx * vy).sqrt()
+}
+
+fn rename_all<'a, F>(
+    names: impl Iterator<Item = &'a str>,
+    f: F,
+) -> Result<Vec<String>, DuplicateName>
+where
+    F: FnMut(&str) -> String,
+{
+    let names = names.map(f).collect::<Vec<_>>();
+    if let Some(name) = find_duplicate(names.iter().map(|n| n.as_str())) {
+        return Err(DuplicateName {
+            name: name.to_owned(),
+        });
+    }
+    Ok(names)
+}
+
+fn find_duplicate<'a>(names: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let mut seen = std::collections::BTreeSet::new();
+    names.into_iter().find(|&name| !seen.insert(name))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateName {
+    pub name: String,
+}
+
+impl fmt::Display for DuplicateName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "duplicate name {:?}", self.name)
+    }
+}
+
+impl std::error::Error for DuplicateName {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScalarizeError {
+    WeightCountMismatch { expected: usize, actual: usize },
+    InvalidWeight { index: usize, weight: f64 },
+}
+
+impl fmt::Display for ScalarizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::WeightCountMismatch { expected, actual } => write!(
+                f,
+                "expected {} weights (one per objective), but got {}",
+                expected, actual
+            ),
+            Self::InvalidWeight { index, weight } => write!(
+                f,
+                "weight #{} must be finite and non-negative, but got {}",
+                index, weight
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScalarizeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    DuplicateSpan(String),
+    DuplicateParam(String),
+    DuplicateValue(String),
+    InvalidRange(String),
+    UnknownStudy(StudyId),
+    ArityMismatch {
+        trial: TrialId,
+        field: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    InvalidSpan {
+        trial: TrialId,
+        index: usize,
+    },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DuplicateSpan(name) => write!(f, "duplicate span name {:?}", name),
+            Self::DuplicateParam(name) => write!(f, "duplicate param name {:?}", name),
+            Self::DuplicateValue(name) => write!(f, "duplicate value name {:?}", name),
+            Self::InvalidRange(name) => write!(f, "param {:?} has an invalid range", name),
+            Self::UnknownStudy(id) => write!(f, "unknown study {:?}", id),
+            Self::ArityMismatch {
+                trial,
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "trial {} has {} {} (expected {})",
+                trial, actual, field, expected
+            ),
+            Self::InvalidSpan { trial, index } => {
+                write!(
+                    f,
+                    "span #{} of trial {} ends before it starts",
+                    index, trial
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompareError {
+    ParamCountMismatch { left: usize, right: usize },
+    ParamMismatch { index: usize, name: String },
+    ValueCountMismatch { left: usize, right: usize },
+    ValueNameMismatch { left: String, right: String },
+    ValueKindMismatch { name: String },
+}
+
+impl fmt::Display for CompareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ParamCountMismatch { left, right } => {
+                write!(f, "different number of params: {} vs {}", left, right)
+            }
+            Self::ParamMismatch { index, name } => {
+                write!(f, "param #{} ({:?}) differs", index, name)
+            }
+            Self::ValueCountMismatch { left, right } => {
+                write!(f, "different number of values: {} vs {}", left, right)
+            }
+            Self::ValueNameMismatch { left, right } => {
+                write!(f, "different value names: {:?} vs {:?}", left, right)
+            }
+            Self::ValueKindMismatch { name } => write!(
+                f,
+                "value {:?} has different directions or constraint flags",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CompareError {}
+
+/// An evaluation result of a trial.
+///
+/// Note that the derived `PartialEq` compares floats with `==`, so records containing NaN
+/// never equal themselves. Use [`EvalRecord::approx_eq`] for float-safe comparison.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EvalRecord {
     pub study: StudyId,
     pub trial: TrialId,
@@ -314,18 +1308,169 @@ pub struct EvalRecord {
     pub values: Vec<f64>,
 }
 
+impl EvalRecord {
+    pub fn wall_clock(&self) -> Option<Duration> {
+        let spans = self
+            .spans
+            .iter()
+            .filter(|s| s.start.is_finite() && s.end.is_finite());
+        let (start, end) = spans.fold(None, |acc: Option<(f64, f64)>, s| match acc {
+            None => Some((s.start, s.end)),
+            Some((start, end)) => Some((start.min(s.start), end.max(s.end))),
+        })?;
+        Span::new(start, end).checked_duration()
+    }
+
+    pub fn value_or_worst(&self, index: usize, direction: Direction) -> f64 {
+        match self.values.get(index) {
+            Some(&v) if v.is_finite() => v,
+            _ => direction.worst_sentinel(),
+        }
+    }
+
+    pub fn latest_span_end(&self) -> Option<f64> {
+        self.spans
+            .iter()
+            .map(|s| s.end)
+            .filter(|v| v.is_finite())
+            .fold(None, |acc: Option<f64>, v| {
+                Some(acc.map_or(v, |acc| acc.max(v)))
+            })
+    }
+
+    pub fn slowest_span<'a>(&self, study: &'a StudyRecord) -> Option<(&'a str, Span)> {
+        study
+            .spans
+            .iter()
+            .zip(&self.spans)
+            .filter_map(|(def, span)| Some((def.name.as_str(), span, span.checked_duration()?)))
+            .max_by_key(|(_, _, d)| *d)
+            .map(|(name, span, _)| (name, span.clone()))
+    }
+
+    /// Returns the duration of each span paired with its name, skipping missing or invalid spans.
+    pub fn phase_durations<'a>(&self, study: &'a StudyRecord) -> Vec<(&'a str, Duration)> {
+        study
+            .spans
+            .iter()
+            .zip(&self.spans)
+            .filter_map(|(def, span)| Some((def.name.as_str(), span.checked_duration()?)))
+            .collect()
+    }
+
+    /// Equivalent to `self.approx_eq_eps(other, 1e-9)`.
+    pub fn approx_eq(&self, other: &Self) -> bool {
+        self.approx_eq_eps(other, 1e-9)
+    }
+
+    /// Like `==`, but NaNs are regarded as equal and other floats are equal if their
+    /// difference is within `eps` relative to the larger magnitude.
+    pub fn approx_eq_eps(&self, other: &Self, eps: f64) -> bool {
+        let eq = |x: f64, y: f64| {
+            (x.is_nan() && y.is_nan()) || x == y || (x - y).abs() <= eps * x.abs().max(y.abs())
+        };
+        let eq_all = |xs: &[f64], ys: &[f64]| {
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(&x, &y)| eq(x, y))
+        };
+
+        self.study == other.study
+            && self.trial == other.trial
+            && self.state == other.state
+            && self.spans.len() == other.spans.len()
+            && self
+                .spans
+                .iter()
+                .zip(&other.spans)
+                .all(|(x, y)| eq(x.start, y.start) && eq(x.end, y.end) && x.meta == y.meta)
+            && eq_all(&self.params, &other.params)
+            && eq_all(&self.values, &other.values)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EvalRecordBuilder {
+    record: EvalRecord,
+}
+
+impl EvalRecordBuilder {
+    pub fn new(study: impl Into<StudyId>, trial: TrialId, state: EvalState) -> Self {
+        Self {
+            record: EvalRecord {
+                study: study.into(),
+                trial,
+                state,
+                spans: Vec::new(),
+                params: Vec::new(),
+                values: Vec::new(),
+            },
+        }
+    }
+
+    pub fn span(mut self, span: Span) -> Self {
+        self.record.spans.push(span);
+        self
+    }
+
+    /// Sets the span at the position of the `SpanDef` named `name` in `study`.
+    ///
+    /// The spans are extended to the number of `SpanDef`s of `study`, and those not set yet have NaN endpoints.
+    /// Panics if `study` has no span named `name`.
+    pub fn span_named(mut self, study: &StudyRecord, name: &str, start: f64, end: f64) -> Self {
+        let Some(i) = study.spans.iter().position(|s| s.name == name) else {
+            panic!("study {:?} has no span named {:?}", study.id, name);
+        };
+        let spans = &mut self.record.spans;
+        if spans.len() < study.spans.len() {
+            spans.resize(study.spans.len(), Span::new(f64::NAN, f64::NAN));
+        }
+        spans[i] = Span::new(start, end);
+        self
+    }
+
+    pub fn param(mut self, v: f64) -> Self {
+        self.record.params.push(v);
+        self
+    }
+
+    pub fn value(mut self, v: f64) -> Self {
+        self.record.values.push(v);
+        self
+    }
+
+    pub fn build(self) -> EvalRecord {
+        self.record
+    }
+}
+
+mod nullable_f64 {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<f64, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let v: Option<f64> = Deserialize::deserialize(deserializer)?;
+        Ok(v.unwrap_or(f64::NAN))
+    }
+
+    pub fn serialize<S>(v: &f64, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let v = if v.is_finite() { Some(*v) } else { None };
+        v.serialize(serializer)
+    }
+}
+
 mod nullable_f64_vec {
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
-    use std::f64::NAN;
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<f64>, D::Error>
     where
         D: Deserializer<'de>,
     {
         let v: Vec<Option<f64>> = Deserialize::deserialize(deserializer)?;
-        Ok(v.into_iter()
-            .map(|v| if let Some(v) = v { v } else { NAN })
-            .collect())
+        Ok(v.into_iter().map(|v| v.unwrap_or(f64::NAN)).collect())
     }
 
     pub fn serialize<S>(v: &[f64], serializer: S) -> Result<S::Ok, S::Error>
@@ -339,3 +1484,952 @@ mod nullable_f64_vec {
         v.serialize(serializer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) fn study(params: Vec<ParamDef>, values: Vec<ValueDef>) -> StudyRecord {
+        StudyRecord {
+            id: "s".to_owned(),
+            name: None,
+            description: None,
+            group: None,
+            attrs: BTreeMap::new(),
+            spans: Vec::new(),
+            params,
+            values,
+        }
+    }
+
+    pub(crate) fn eval(
+        trial: TrialId,
+        state: EvalState,
+        params: Vec<f64>,
+        values: Vec<f64>,
+    ) -> EvalRecord {
+        EvalRecord {
+            study: "s".to_owned(),
+            trial,
+            state,
+            spans: Vec::new(),
+            params,
+            values,
+        }
+    }
+
+    #[test]
+    fn scale_apply_and_invert_round_trip() {
+        for &x in &[1e-3, 0.5, 1.0, 42.0] {
+            for scale in [Scale::Linear, Scale::Log] {
+                let y = scale.invert(scale.apply(x));
+                assert!((y - x).abs() <= 1e-12 * x, "{:?}: {} -> {}", scale, x, y);
+            }
+        }
+        assert_eq!(Scale::Linear.apply(-2.0), -2.0);
+        assert_eq!(Scale::Log.apply(1.0), 0.0);
+        assert!(Scale::Log.apply(0.0).is_nan());
+        assert!(Scale::Log.apply(-1.0).is_nan());
+    }
+
+    #[test]
+    fn observed_value_range_covers_finite_complete_values() {
+        let study = study(
+            vec![ParamDef::continuous("x", 0.0, 1.0)],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let evals = vec![
+            eval(0, EvalState::Complete, vec![0.1], vec![3.0]),
+            eval(1, EvalState::Complete, vec![0.2], vec![f64::NAN]),
+            eval(2, EvalState::Complete, vec![0.3], vec![-1.5]),
+            eval(3, EvalState::Interim, vec![0.4], vec![-10.0]),
+            eval(4, EvalState::Complete, vec![0.5], vec![f64::INFINITY]),
+            eval(5, EvalState::Complete, vec![0.6], vec![2.0]),
+        ];
+        assert_eq!(
+            study.observed_value_range(&evals, 0),
+            Some(ValueRange {
+                min: -1.5,
+                max: 3.0
+            })
+        );
+    }
+
+    #[test]
+    fn observed_value_range_of_all_nan_column_is_none() {
+        let study = study(
+            vec![ParamDef::continuous("x", 0.0, 1.0)],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let evals = vec![
+            eval(0, EvalState::Complete, vec![0.1], vec![f64::NAN]),
+            eval(1, EvalState::Complete, vec![0.2], vec![f64::NAN]),
+        ];
+        assert_eq!(study.observed_value_range(&evals, 0), None);
+        assert_eq!(study.observed_value_range(&evals, 1), None);
+        assert_eq!(study.observed_value_range(&[], 0), None);
+    }
+
+    #[test]
+    fn records_compare_structurally() {
+        let s = study(
+            vec![ParamDef::continuous("x", 0.0, 1.0)],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        assert_eq!(s, s.clone());
+        assert_ne!(s, s.clone().with_attr("seed", "1"));
+        assert_eq!(Record::Study(s.clone()), Record::Study(s.clone()));
+
+        let a = eval(0, EvalState::Complete, vec![0.5], vec![1.0]);
+        assert_eq!(a, a.clone());
+        assert_ne!(a, eval(1, EvalState::Complete, vec![0.5], vec![1.0]));
+        assert_ne!(a, eval(0, EvalState::Complete, vec![0.5], vec![2.0]));
+        assert_ne!(Record::Eval(a.clone()), Record::Study(s));
+
+        // NaN never equals itself, so `approx_eq` is needed for such records.
+        let nan = eval(0, EvalState::Failed, vec![0.5], vec![f64::NAN]);
+        assert_ne!(nan, nan.clone());
+        assert!(nan.approx_eq(&nan.clone()));
+    }
+
+    #[test]
+    fn flatten_decodes_params_and_names_values() {
+        let mut s = study(
+            vec![
+                ParamDef::log_continuous("lr", 1e-4, 1.0),
+                ParamDef::categorical("opt", vec!["sgd".to_owned(), "adam".to_owned()]),
+            ],
+            vec![
+                ValueDef::new("loss", Direction::Minimize),
+                ValueDef::new("acc", Direction::Maximize),
+            ],
+        );
+        s.spans = vec![SpanDef::new("train")];
+        let mut e = eval(3, EvalState::Complete, vec![0.01, 1.0], vec![0.25, 0.9]);
+        e.spans = vec![Span::new(10.0, 12.5)];
+
+        let flat = s.flatten(&e);
+        assert_eq!(
+            flat,
+            io::FlatEval {
+                study: "s".to_owned(),
+                trial: 3,
+                state: EvalState::Complete,
+                params: vec![
+                    ("lr".to_owned(), ParamValue::Numerical(0.01)),
+                    ("opt".to_owned(), ParamValue::Categorical("adam".to_owned())),
+                ],
+                values: vec![("loss".to_owned(), 0.25), ("acc".to_owned(), 0.9)],
+                wall_clock: Some(Duration::from_millis(2500)),
+            }
+        );
+
+        let json = serde_json::to_value(&flat).unwrap();
+        assert_eq!(json["params"][1], serde_json::json!(["opt", "adam"]));
+        assert_eq!(json["wall_clock"], serde_json::json!(2.5));
+    }
+
+    #[test]
+    fn cardinality_of_each_range_kind() {
+        assert_eq!(ParamRange::continuous(0.0, 1.0).cardinality(), None);
+        assert_eq!(ParamRange::log_continuous(1e-3, 1.0).cardinality(), None);
+        assert_eq!(ParamRange::discrete(0.0, 10.0, 2.0).cardinality(), Some(6));
+        assert_eq!(ParamRange::discrete(0.0, 10.0, 3.0).cardinality(), Some(4));
+        assert_eq!(ParamRange::discrete(0.0, 1.0, 0.1).cardinality(), Some(11));
+        assert_eq!(ParamRange::discrete(2.0, 2.0, 1.0).cardinality(), Some(1));
+        assert_eq!(ParamRange::discrete(0.0, 1.0, 0.0).cardinality(), None);
+        assert_eq!(ParamRange::discrete(0.0, 1e30, 1e-10).cardinality(), None);
+        assert_eq!(
+            ParamRange::discrete(0.0, 2f64.powi(64), 1.0).cardinality(),
+            None
+        );
+        assert_eq!(
+            ParamRange::discrete(0.0, f64::INFINITY, 1.0).cardinality(),
+            None
+        );
+        assert_eq!(
+            ParamRange::categorical(vec!["a".to_owned(), "b".to_owned(), "c".to_owned()])
+                .cardinality(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn span_helpers_handle_empty_spans() {
+        let mut s = study(
+            vec![ParamDef::continuous("x", 0.0, 1.0)],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        s.spans = vec![SpanDef::new("train"), SpanDef::new("eval")];
+        let e = eval(0, EvalState::Complete, vec![0.5], vec![1.0]);
+        assert!(e.spans.is_empty());
+
+        assert_eq!(e.wall_clock(), None);
+        assert_eq!(e.latest_span_end(), None);
+        assert!(e.phase_durations(&s).is_empty());
+        assert_eq!(e.slowest_span(&s), None);
+        assert_eq!(s.flatten(&e).wall_clock, None);
+
+        let evals = vec![e];
+        assert_eq!(io::cumulative_time(&evals), vec![(0, Duration::ZERO)]);
+        assert!(io::throughput(&evals, 1.0).is_empty());
+        assert_eq!(
+            io::best_within_time(&evals, 0, Direction::Minimize, 1.0),
+            evals.first()
+        );
+    }
+
+    #[test]
+    fn rung_of_reads_the_rung_param() {
+        let s = study(
+            vec![
+                ParamDef::continuous("x", 0.0, 1.0),
+                ParamDef::discrete("epochs", 0.0, 100.0, 1.0),
+            ],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let e = eval(0, EvalState::Complete, vec![0.5, 3.0], vec![1.0]);
+        assert_eq!(s.rung_of(&e), None);
+
+        let s = s.with_attr(RUNG_PARAM_ATTR, "epochs");
+        assert_eq!(s.rung_of(&e), Some(3));
+        assert_eq!(
+            s.rung_of(&eval(1, EvalState::Complete, vec![0.5, 2.5], vec![1.0])),
+            None
+        );
+        assert_eq!(
+            s.rung_of(&eval(2, EvalState::Complete, vec![0.5], vec![1.0])),
+            None
+        );
+    }
+
+    #[test]
+    fn non_finite_span_endpoints_round_trip_as_null() {
+        let mut e = eval(0, EvalState::Failed, vec![0.5], vec![f64::NAN]);
+        e.spans = vec![Span::new(1.0, f64::INFINITY), Span::new(f64::NAN, 2.0)];
+
+        let json = serde_json::to_string(&e).unwrap();
+        assert!(
+            json.contains(r#""spans":[{"start":1.0,"end":null},{"start":null,"end":2.0}]"#),
+            "{}",
+            json
+        );
+
+        let parsed: EvalRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.spans[0].start, 1.0);
+        assert!(parsed.spans[0].end.is_nan());
+        assert!(parsed.spans[1].start.is_nan());
+        assert_eq!(parsed.spans[1].end, 2.0);
+    }
+
+    #[test]
+    fn objective_index_by_name() {
+        let s = study(
+            Vec::new(),
+            vec![
+                ValueDef::new("loss", Direction::Minimize),
+                ValueDef::new("acc", Direction::Maximize),
+            ],
+        );
+        assert_eq!(s.objective_index("acc"), Ok(1));
+
+        let e = s.objective_index("f1").unwrap_err();
+        assert_eq!(e.available, vec!["loss".to_owned(), "acc".to_owned()]);
+        assert_eq!(
+            e.to_string(),
+            r#"unknown value "f1" (available: ["loss", "acc"])"#
+        );
+    }
+
+    #[test]
+    fn categorical_matrix_decodes_complete_evals() {
+        let s = study(
+            vec![
+                ParamDef::continuous("x", 0.0, 1.0),
+                ParamDef::categorical("opt", vec!["sgd".to_owned(), "adam".to_owned()]),
+            ],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let evals = vec![
+            eval(0, EvalState::Complete, vec![0.1, 0.0], vec![1.0]),
+            eval(1, EvalState::Complete, vec![0.2, 1.0], vec![1.0]),
+            eval(2, EvalState::Interim, vec![0.3, 1.0], vec![1.0]),
+            eval(3, EvalState::Complete, vec![0.4, f64::NAN], vec![1.0]),
+            eval(4, EvalState::Complete, vec![0.5, 2.0], vec![1.0]),
+        ];
+        let matrix = s.categorical_matrix(&evals);
+        assert_eq!(matrix.len(), 1);
+        assert_eq!(
+            matrix["opt"],
+            vec![Some("sgd".to_owned()), Some("adam".to_owned()), None, None]
+        );
+    }
+
+    #[test]
+    fn marginal_best_finds_the_optimum_region() {
+        let s = study(
+            vec![
+                ParamDef::continuous("x", 0.0, 1.0),
+                ParamDef::categorical("opt", vec!["sgd".to_owned(), "adam".to_owned()]),
+            ],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let evals = [0.05, 0.2, 0.3, 0.45, 0.55, 0.65, 0.8, 0.95]
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| {
+                eval(
+                    i as TrialId,
+                    EvalState::Complete,
+                    vec![x, 0.0],
+                    vec![(x - 0.6f64).powi(2)],
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let curve = s
+            .marginal_best(&evals, "x", 0, Direction::Minimize, 4)
+            .unwrap();
+        let centers = curve.iter().map(|(c, _)| *c).collect::<Vec<_>>();
+        assert_eq!(centers, vec![0.125, 0.375, 0.625, 0.875]);
+        let (best_center, best) = curve
+            .iter()
+            .copied()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        assert_eq!(best_center, 0.625);
+        assert!((best - 0.0025).abs() < 1e-12);
+        assert!((curve[1].1 - 0.0225).abs() < 1e-12);
+
+        assert_eq!(
+            s.marginal_best(&evals, "opt", 0, Direction::Minimize, 4),
+            None
+        );
+        assert_eq!(
+            s.marginal_best(&evals, "y", 0, Direction::Minimize, 4),
+            None
+        );
+        assert_eq!(
+            s.marginal_best(&evals, "x", 0, Direction::Minimize, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn value_or_worst_replaces_missing_values() {
+        let e = eval(0, EvalState::Complete, Vec::new(), vec![0.5, f64::NAN]);
+        for direction in [Direction::Minimize, Direction::Maximize] {
+            assert_eq!(e.value_or_worst(0, direction), 0.5);
+            assert_eq!(e.value_or_worst(1, direction), direction.worst_sentinel());
+            assert_eq!(e.value_or_worst(2, direction), direction.worst_sentinel());
+        }
+        assert_eq!(e.value_or_worst(1, Direction::Minimize), f64::INFINITY);
+        assert_eq!(e.value_or_worst(1, Direction::Maximize), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn with_attr_and_set_attr() {
+        let mut s = study(Vec::new(), Vec::new()).with_attr("seed", "1");
+        assert_eq!(s.attrs.get("seed").map(|v| v.as_str()), Some("1"));
+
+        assert_eq!(s.set_attr("optimizer", "tpe"), None);
+        assert_eq!(s.set_attr("seed", "2"), Some("1".to_owned()));
+        assert_eq!(s.attrs.len(), 2);
+        assert_eq!(s.attrs["seed"], "2");
+        assert_eq!(s.with_attr("seed", "3").attrs["seed"], "3");
+    }
+
+    #[test]
+    fn is_feasible_checks_constraint_values() {
+        let s = study(
+            Vec::new(),
+            vec![
+                ValueDef::new("loss", Direction::Minimize),
+                ValueDef::new_constraint("memory_excess"),
+            ],
+        );
+        assert!(s.is_feasible(&eval(0, EvalState::Complete, Vec::new(), vec![1.0, -0.5])));
+        assert!(s.is_feasible(&eval(1, EvalState::Complete, Vec::new(), vec![1.0, 0.0])));
+        assert!(!s.is_feasible(&eval(2, EvalState::Complete, Vec::new(), vec![1.0, 0.1])));
+        assert!(!s.is_feasible(&eval(
+            3,
+            EvalState::Complete,
+            Vec::new(),
+            vec![1.0, f64::NAN]
+        )));
+        assert!(!s.is_feasible(&eval(4, EvalState::Infeasible, Vec::new(), vec![1.0, -1.0])));
+
+        // `constraint` defaults to `false` for older logs.
+        let v: ValueDef =
+            serde_json::from_str(r#"{"name":"loss","direction":"MINIMIZE"}"#).unwrap();
+        assert!(!v.constraint);
+        assert_eq!(
+            serde_json::to_string(&s.values[1]).unwrap(),
+            r#"{"name":"memory_excess","direction":"MINIMIZE","constraint":true}"#
+        );
+    }
+
+    #[test]
+    fn enumerate_grid_yields_the_product_of_grids() {
+        let s = study(
+            vec![
+                ParamDef::discrete("depth", 1.0, 3.0, 1.0),
+                ParamDef::categorical("opt", vec!["sgd".to_owned(), "adam".to_owned()]),
+            ],
+            Vec::new(),
+        );
+        assert_eq!(s.grid_size(), Some(6));
+        let grid = s.enumerate_grid().unwrap().collect::<Vec<_>>();
+        assert_eq!(
+            grid,
+            vec![
+                vec![1.0, 0.0],
+                vec![1.0, 1.0],
+                vec![2.0, 0.0],
+                vec![2.0, 1.0],
+                vec![3.0, 0.0],
+                vec![3.0, 1.0],
+            ]
+        );
+
+        let mut s = s;
+        s.params.push(ParamDef::continuous("x", 0.0, 1.0));
+        assert!(s.enumerate_grid().is_none());
+        assert_eq!(s.grid_size(), None);
+    }
+
+    #[test]
+    fn enumerate_grid_is_lazy() {
+        let s = study(
+            vec![
+                ParamDef::discrete("n", 0.0, 1e11, 1.0),
+                ParamDef::categorical("opt", vec!["sgd".to_owned(), "adam".to_owned()]),
+            ],
+            Vec::new(),
+        );
+        let grid = s.enumerate_grid().unwrap().take(3).collect::<Vec<_>>();
+        assert_eq!(grid, vec![vec![0.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0]]);
+    }
+
+    #[test]
+    fn schema_only_drops_attrs() {
+        let mut s = study(
+            vec![ParamDef::continuous("x", 0.0, 1.0)],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        )
+        .with_attr("seed", "1");
+        s.spans = vec![SpanDef::new("train")];
+
+        let schema = s.schema_only();
+        assert!(schema.attrs.is_empty());
+        assert_eq!(schema.id, s.id);
+        assert_eq!(schema.spans, s.spans);
+        assert_eq!(schema.params, s.params);
+        assert_eq!(schema.values, s.values);
+        assert_eq!(schema, s.with_attr("seed", "2").schema_only());
+    }
+
+    #[test]
+    fn add_derived_value_extends_every_eval() {
+        let mut s = study(
+            Vec::new(),
+            vec![
+                ValueDef::new("acc", Direction::Maximize),
+                ValueDef::new("latency", Direction::Minimize),
+            ],
+        );
+        let mut evals = vec![
+            eval(0, EvalState::Complete, Vec::new(), vec![0.9, 2.0]),
+            eval(1, EvalState::Complete, Vec::new(), vec![0.8, 4.0]),
+            eval(2, EvalState::Failed, Vec::new(), vec![0.5]),
+        ];
+        s.add_derived_value("acc_per_latency", Direction::Maximize, &mut evals, |v| {
+            v[0] / v[1]
+        });
+
+        assert_eq!(s.values.len(), 3);
+        assert_eq!(s.values[2].name, "acc_per_latency");
+        assert!(evals.iter().all(|e| e.values.len() == 3));
+        assert_eq!(evals[0].values, vec![0.9, 2.0, 0.45]);
+        assert_eq!(evals[1].values, vec![0.8, 4.0, 0.2]);
+        assert_eq!(evals[2].values[0], 0.5);
+        assert!(evals[2].values[1..].iter().all(|v| v.is_nan()));
+    }
+
+    #[test]
+    fn annotated_spans_round_trip_their_metadata() {
+        let json = r#"{"start":1.0,"end":2.5,"gpu":0,"name":"train"}"#;
+        let span: Span = serde_json::from_str(json).unwrap();
+        assert_eq!((span.start, span.end), (1.0, 2.5));
+        let meta = span.meta.as_ref().unwrap();
+        assert_eq!(meta["name"], "train");
+        assert_eq!(meta["gpu"], 0);
+        assert_eq!(serde_json::to_string(&span).unwrap(), json);
+
+        let plain: Span = serde_json::from_str(r#"{"start":1.0,"end":2.5}"#).unwrap();
+        assert_eq!(plain, Span::new(1.0, 2.5));
+        assert_eq!(
+            serde_json::to_string(&plain).unwrap(),
+            r#"{"start":1.0,"end":2.5}"#
+        );
+    }
+
+    #[test]
+    fn categorical_combinations_counts_complete_tuples() {
+        let s = study(
+            vec![
+                ParamDef::categorical("opt", vec!["sgd".to_owned(), "adam".to_owned()]),
+                ParamDef::continuous("lr", 0.0, 1.0),
+                ParamDef::categorical("act", vec!["relu".to_owned(), "tanh".to_owned()]),
+            ],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let evals = vec![
+            eval(0, EvalState::Complete, vec![1.0, 0.1, 0.0], vec![1.0]),
+            eval(1, EvalState::Complete, vec![1.0, 0.2, 0.0], vec![1.0]),
+            eval(2, EvalState::Complete, vec![0.0, 0.3, 1.0], vec![1.0]),
+            eval(3, EvalState::Interim, vec![0.0, 0.4, 1.0], vec![1.0]),
+            eval(4, EvalState::Complete, vec![0.0, 0.5, f64::NAN], vec![1.0]),
+        ];
+        let pair = |a: &str, b: &str| vec![a.to_owned(), b.to_owned()];
+        assert_eq!(
+            s.categorical_combinations(&evals),
+            vec![(pair("adam", "relu"), 2), (pair("sgd", "tanh"), 1)]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn map_names_renames_and_keeps_uniqueness() {
+        let mut s = study(
+            vec![
+                ParamDef::continuous("lr", 0.0, 1.0),
+                ParamDef::continuous("momentum", 0.0, 1.0),
+            ],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        s.map_param_names(|name| {
+            if name == "lr" {
+                "learning_rate".to_owned()
+            } else {
+                name.to_owned()
+            }
+        })
+        .unwrap();
+        let names = s.params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["learning_rate", "momentum"]);
+
+        let before = s.clone();
+        assert_eq!(
+            s.map_param_names(|_| "x".to_owned()),
+            Err(DuplicateName {
+                name: "x".to_owned()
+            })
+        );
+        assert_eq!(s, before);
+
+        s.map_value_names(|name| format!("val_{}", name)).unwrap();
+        assert_eq!(s.values[0].name, "val_loss");
+    }
+
+    #[test]
+    fn fidelity_importance_scores_the_dominant_param() {
+        let s = study(
+            vec![
+                ParamDef::continuous("x", 0.0, 1.0),
+                ParamDef::categorical("opt", vec!["sgd".to_owned(), "adam".to_owned()]),
+                ParamDef::continuous("y", 0.0, 1.0),
+            ],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let mut evals = Vec::new();
+        let xs = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6];
+        let ys = [0.5, 0.2, 0.6, 0.1, 0.4, 0.3];
+        for (trial, (&x, &y)) in xs.iter().zip(&ys).enumerate() {
+            let trial = trial as TrialId;
+            for _ in 0..2 {
+                evals.push(eval(trial, EvalState::Interim, vec![x, 0.0, y], vec![1.0]));
+            }
+            evals.push(eval(trial, EvalState::Complete, vec![x, 0.0, y], vec![x]));
+        }
+        // A pruned trial that would contradict the trend if it were used.
+        evals.push(eval(6, EvalState::Interim, vec![0.9, 0.0, 0.9], vec![1.0]));
+        evals.push(eval(6, EvalState::Complete, vec![0.9, 0.0, 0.9], vec![0.0]));
+
+        let scores = s.fidelity_importance(&evals, 0);
+        assert_eq!(scores.len(), 2);
+        assert_eq!(scores[0], ("x".to_owned(), 1.0));
+        assert_eq!(scores[1].0, "y");
+        assert!((scores[1].1 - 9.0 / 35.0).abs() < 1e-12, "{:?}", scores);
+
+        assert_eq!(
+            s.fidelity_importance(&[], 0),
+            vec![("x".to_owned(), 0.0), ("y".to_owned(), 0.0)]
+        );
+    }
+
+    #[test]
+    fn to_columns_aligns_rows() {
+        let s = study(
+            vec![
+                ParamDef::continuous("x", 0.0, 1.0),
+                ParamDef::continuous("y", 0.0, 1.0),
+            ],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let mut other = eval(9, EvalState::Complete, vec![0.9, 0.9], vec![9.0]);
+        other.study = "other".to_owned();
+        let evals = vec![
+            eval(0, EvalState::Complete, vec![0.1, 0.2], vec![1.0]),
+            other,
+            eval(1, EvalState::Failed, vec![0.3], vec![]),
+            eval(2, EvalState::Interim, vec![0.5, 0.6], vec![3.0]),
+        ];
+
+        let columns = s.to_columns(&evals);
+        assert_eq!(columns.trial, vec![0, 1, 2]);
+        assert_eq!(
+            columns.state,
+            vec![EvalState::Complete, EvalState::Failed, EvalState::Interim]
+        );
+        assert!(columns
+            .params
+            .values()
+            .chain(columns.values.values())
+            .all(|c| c.len() == 3));
+        assert_eq!(columns.params["x"], vec![0.1, 0.3, 0.5]);
+        assert_eq!(columns.params["y"][0], 0.2);
+        assert!(columns.params["y"][1].is_nan());
+        assert_eq!(columns.values["loss"][2], 3.0);
+        assert!(columns.values["loss"][1].is_nan());
+    }
+
+    #[test]
+    fn slowest_span_returns_the_dominant_span() {
+        let mut s = study(Vec::new(), Vec::new());
+        s.spans = vec![
+            SpanDef::new("load"),
+            SpanDef::new("train"),
+            SpanDef::new("eval"),
+        ];
+        let mut e = eval(0, EvalState::Complete, Vec::new(), Vec::new());
+        e.spans = vec![
+            Span::new(0.0, 1.0),
+            Span::new(1.0, 9.0),
+            Span::new(9.0, f64::NAN),
+        ];
+        assert_eq!(e.slowest_span(&s), Some(("train", Span::new(1.0, 9.0))));
+        assert_eq!(
+            e.phase_durations(&s),
+            vec![
+                ("load", Duration::from_secs(1)),
+                ("train", Duration::from_secs(8))
+            ]
+        );
+    }
+
+    #[test]
+    fn approx_eq_eps_at_the_tolerance_boundary() {
+        let with_value = |v| eval(0, EvalState::Complete, vec![0.5], vec![v, f64::NAN]);
+        let base = with_value(100.0);
+
+        assert!(base.approx_eq_eps(&with_value(100.0 + 0.9e-4), 1e-6));
+        assert!(!base.approx_eq_eps(&with_value(100.0 + 1.1e-4), 1e-6));
+        assert!(base.approx_eq_eps(&with_value(100.0 - 0.9e-4), 1e-6));
+        assert!(!base.approx_eq_eps(&with_value(100.0 - 1.1e-4), 1e-6));
+
+        assert!(base.approx_eq(&with_value(100.0 + 0.9e-7)));
+        assert!(!base.approx_eq(&with_value(100.0 + 1.1e-7)));
+        assert!(!base.approx_eq(&eval(0, EvalState::Complete, vec![0.5], vec![100.0])));
+    }
+
+    #[test]
+    fn effective_dimensions_skips_frozen_params() {
+        let s = study(
+            vec![
+                ParamDef::continuous("a", 0.0, 1.0),
+                ParamDef::continuous("b", 0.0, 1.0),
+                ParamDef::continuous("frozen", 0.0, 1.0),
+                ParamDef::categorical("c", vec!["x".to_owned(), "y".to_owned()]),
+            ],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let evals = vec![
+            eval(0, EvalState::Complete, vec![0.1, 0.2, 0.5, 0.0], vec![1.0]),
+            eval(1, EvalState::Complete, vec![0.3, 0.2, 0.5, 1.0], vec![1.0]),
+            eval(
+                2,
+                EvalState::Complete,
+                vec![0.3, 0.4, f64::NAN, 1.0],
+                vec![1.0],
+            ),
+            eval(3, EvalState::Failed, vec![0.3, 0.4, 0.7, 1.0], vec![1.0]),
+        ];
+        assert_eq!(s.effective_dimensions(&evals), 3);
+        assert_eq!(s.effective_dimensions(&evals[..1]), 0);
+    }
+
+    #[test]
+    fn scalarize_validates_weights() {
+        let s = study(
+            Vec::new(),
+            vec![
+                ValueDef::new("loss", Direction::Minimize),
+                ValueDef::new_constraint("memory_excess"),
+                ValueDef::new("acc", Direction::Maximize),
+            ],
+        );
+        let e = eval(0, EvalState::Complete, Vec::new(), vec![2.0, -1.0, 0.5]);
+        assert_eq!(s.scalarize(&e, &[1.0, 4.0]), Ok(0.0));
+
+        let err = s.scalarize(&e, &[1.0]).unwrap_err();
+        assert_eq!(
+            err,
+            ScalarizeError::WeightCountMismatch {
+                expected: 2,
+                actual: 1
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "expected 2 weights (one per objective), but got 1"
+        );
+
+        let err = s.scalarize(&e, &[1.0, -0.5]).unwrap_err();
+        assert_eq!(
+            err,
+            ScalarizeError::InvalidWeight {
+                index: 1,
+                weight: -0.5
+            }
+        );
+        assert!(matches!(
+            s.scalarize(&e, &[f64::NAN, 1.0]),
+            Err(ScalarizeError::InvalidWeight { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn record_validate_dispatches_by_kind() {
+        let mut s = study(
+            vec![
+                ParamDef::continuous("x", 0.0, 1.0),
+                ParamDef::continuous("y", 0.0, 1.0),
+            ],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        s.spans = vec![SpanDef::new("train")];
+        let known = vec![(s.id.clone(), s.clone())].into_iter().collect();
+
+        let mut valid = eval(0, EvalState::Complete, vec![0.1, 0.2], vec![1.0]);
+        valid.spans = vec![Span::new(0.0, 1.0)];
+        assert_eq!(Record::Eval(valid.clone()).validate(&known), Ok(()));
+        assert_eq!(Record::Study(s.clone()).validate(&known), Ok(()));
+
+        let mut short = valid.clone();
+        short.params.pop();
+        assert_eq!(
+            Record::Eval(short).validate(&known),
+            Err(ValidationError::ArityMismatch {
+                trial: 0,
+                field: "params",
+                expected: 2,
+                actual: 1
+            })
+        );
+
+        let mut reversed = valid.clone();
+        reversed.spans = vec![Span::new(2.0, 1.0)];
+        assert_eq!(
+            Record::Eval(reversed).validate(&known),
+            Err(ValidationError::InvalidSpan { trial: 0, index: 0 })
+        );
+
+        let mut unknown = valid;
+        unknown.study = "t".to_owned();
+        assert_eq!(
+            Record::Eval(unknown).validate(&known),
+            Err(ValidationError::UnknownStudy("t".to_owned()))
+        );
+
+        s.params[1].name = "x".to_owned();
+        assert_eq!(
+            Record::Study(s).validate(&BTreeMap::new()),
+            Err(ValidationError::DuplicateParam("x".to_owned()))
+        );
+    }
+
+    #[test]
+    fn study_name_and_description_round_trip() {
+        let unnamed = study(Vec::new(), Vec::new());
+        let json = serde_json::to_string(&unnamed).unwrap();
+        assert!(
+            !json.contains("name") && !json.contains("description"),
+            "{}",
+            json
+        );
+        assert_eq!(serde_json::from_str::<StudyRecord>(&json).unwrap(), unnamed);
+
+        let named = unnamed
+            .clone()
+            .with_name("MNIST MLP")
+            .with_description("Tuning a two-layer MLP");
+        let json = serde_json::to_string(&named).unwrap();
+        let parsed = serde_json::from_str::<StudyRecord>(&json).unwrap();
+        assert_eq!(parsed.name.as_deref(), Some("MNIST MLP"));
+        assert_eq!(
+            parsed.description.as_deref(),
+            Some("Tuning a two-layer MLP")
+        );
+        assert_eq!(parsed, named);
+
+        assert_eq!(named.schema_only(), unnamed.schema_only());
+    }
+
+    #[test]
+    fn choice_index_map_matches_choices_order() {
+        let choices = vec!["sgd".to_owned(), "adam".to_owned(), "rmsprop".to_owned()];
+        let range = ParamRange::categorical(choices.clone());
+        let map = range.choice_index_map().unwrap();
+        assert_eq!(map.len(), choices.len());
+        for (i, c) in choices.iter().enumerate() {
+            assert_eq!(map[c.as_str()], i);
+            assert_eq!(range.choice(i as f64), Some(c.as_str()));
+        }
+        assert_eq!(map.get("adagrad"), None);
+
+        let duplicated = ParamRange::categorical(vec!["a".to_owned(), "a".to_owned()]);
+        assert_eq!(duplicated.choice_index_map().unwrap()["a"], 0);
+        assert_eq!(ParamRange::continuous(0.0, 1.0).choice_index_map(), None);
+    }
+
+    #[test]
+    fn split_params_separates_numericals_and_categoricals() {
+        let s = study(
+            vec![
+                ParamDef::continuous("lr", 0.0, 1.0),
+                ParamDef::categorical("opt", vec!["sgd".to_owned(), "adam".to_owned()]),
+                ParamDef::discrete("layers", 1.0, 4.0, 1.0),
+            ],
+            Vec::new(),
+        );
+        let e = eval(0, EvalState::Complete, vec![0.1, 1.0, 3.0], Vec::new());
+        assert_eq!(s.split_params(&e), (vec![0.1, 3.0], vec![1]));
+
+        let e = eval(1, EvalState::Complete, vec![0.1, 5.0], Vec::new());
+        let (numericals, categoricals) = s.split_params(&e);
+        assert_eq!(numericals[0], 0.1);
+        assert!(numericals[1].is_nan());
+        assert_eq!(categoricals, vec![2]);
+    }
+
+    #[test]
+    fn eval_states_are_ordered_by_finality() {
+        assert!(EvalState::Complete > EvalState::Interim);
+        assert!(EvalState::Failed > EvalState::Interim);
+        assert!(EvalState::Infeasible > EvalState::Interim);
+        assert!(EvalState::Complete > EvalState::Failed);
+
+        let states = [
+            EvalState::Interim,
+            EvalState::Complete,
+            EvalState::Interim,
+            EvalState::Failed,
+        ];
+        assert_eq!(states.iter().max(), Some(&EvalState::Complete));
+    }
+
+    fn study_with_spans() -> StudyRecord {
+        let mut s = study(
+            vec![ParamDef::continuous("x", 0.0, 1.0)],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        s.spans = vec![SpanDef::new("train"), SpanDef::new("eval")];
+        s
+    }
+
+    #[test]
+    fn span_named_places_spans_by_schema_order() {
+        let s = study_with_spans();
+        let e = EvalRecordBuilder::new("s", 0, EvalState::Complete)
+            .span_named(&s, "eval", 5.0, 6.0)
+            .span_named(&s, "train", 0.0, 5.0)
+            .param(0.5)
+            .value(1.0)
+            .build();
+        assert_eq!(e.spans, vec![Span::new(0.0, 5.0), Span::new(5.0, 6.0)]);
+        assert_eq!(s.validate_eval(&e), Ok(()));
+
+        let partial = EvalRecordBuilder::new("s", 1, EvalState::Interim)
+            .span_named(&s, "train", 0.0, 1.0)
+            .param(0.5)
+            .value(1.0)
+            .build();
+        assert_eq!(partial.spans.len(), 2);
+        assert!(partial.spans[1].start.is_nan() && partial.spans[1].end.is_nan());
+        assert_eq!(s.validate_eval(&partial), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "no span named \"trian\"")]
+    fn span_named_panics_on_unknown_names() {
+        let s = study_with_spans();
+        EvalRecordBuilder::new("s", 0, EvalState::Complete).span_named(&s, "trian", 0.0, 1.0);
+    }
+
+    #[test]
+    fn assert_comparable_checks_params_and_values() {
+        let a = study(
+            vec![ParamDef::continuous("x", 0.0, 1.0)],
+            vec![ValueDef::new("loss", Direction::Minimize)],
+        );
+        let mut b = a.clone().with_attr("optimizer", "random");
+        b.id = "t".to_owned();
+        assert_eq!(a.assert_comparable(&b), Ok(()));
+
+        let mut maximized = b.clone();
+        maximized.values[0].direction = Direction::Maximize;
+        let err = a.assert_comparable(&maximized).unwrap_err();
+        assert_eq!(
+            err,
+            CompareError::ValueKindMismatch {
+                name: "loss".to_owned()
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            r#"value "loss" has different directions or constraint flags"#
+        );
+
+        let mut wider = b.clone();
+        wider.params[0] = ParamDef::continuous("x", 0.0, 2.0);
+        assert_eq!(
+            a.assert_comparable(&wider),
+            Err(CompareError::ParamMismatch {
+                index: 0,
+                name: "x".to_owned()
+            })
+        );
+
+        let mut renamed = b;
+        renamed.values[0].name = "error".to_owned();
+        assert!(matches!(
+            a.assert_comparable(&renamed),
+            Err(CompareError::ValueNameMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn study_group_round_trips() {
+        let ungrouped = study(Vec::new(), Vec::new());
+        assert!(!serde_json::to_string(&ungrouped).unwrap().contains("group"));
+
+        let grouped = ungrouped.clone().with_group("mlp-seeds");
+        let json = serde_json::to_string(&grouped).unwrap();
+        assert!(json.contains(r#""group":"mlp-seeds""#), "{}", json);
+        assert_eq!(serde_json::from_str::<StudyRecord>(&json).unwrap(), grouped);
+
+        assert_eq!(grouped.schema_only(), ungrouped.schema_only());
+    }
+}